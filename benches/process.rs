@@ -0,0 +1,93 @@
+//! Compares rasterizing emoji by parsing the themed document for each one,
+//! as builds used to, against parsing it once for each batch of emoji that
+//! rayon splits the work into, run with `cargo bench`.
+
+use emoji_crafter::prelude::*;
+use rayon::prelude::*;
+use std::time::{Duration, Instant};
+
+fn main() {
+    println!(
+        "{: >8} {: >16} {: >16} {: >8}",
+        "emoji", "parse per emoji", "parse per batch", "speedup"
+    );
+
+    for count in [50, 200, 800] {
+        let document = document(count);
+        let svg = document.files[0].svg.replace(
+            "<style></style>",
+            "<style>.background { fill: #000000; }</style>",
+        );
+        let data = svg.as_bytes();
+        let emojis: Vec<_> = document.emojis.values().cloned().collect();
+        let per_emoji = time(|| {
+            emojis.par_iter().for_each(|emoji| {
+                let rtree = emoji_renderer::parse_tree(data);
+
+                emoji_renderer::process(emoji, &rtree);
+            });
+        });
+        let per_batch = time(|| {
+            emojis.par_iter().for_each_init(
+                || emoji_renderer::parse_tree(data),
+                |rtree, emoji| {
+                    emoji_renderer::process(emoji, rtree);
+                },
+            );
+        });
+
+        println!(
+            "{: >8} {: >14}ms {: >14}ms {: >7.1}x",
+            count,
+            per_emoji.as_millis(),
+            per_batch.as_millis(),
+            per_emoji.as_secs_f64() / per_batch.as_secs_f64()
+        );
+    }
+}
+
+fn time<F: FnOnce()>(f: F) -> Duration {
+    let start = Instant::now();
+
+    f();
+    start.elapsed()
+}
+
+/// Generate a document with a grid of simple emoji
+fn document(count: usize) -> Document {
+    let columns = 20;
+    let groups: String = (0..count)
+        .map(|index| {
+            let x = (index % columns) * 128;
+            let y = (index / columns) * 128;
+
+            format!(
+                r##"<g id="g{index}"><desc>type = "image"
+name = "emoji{index}"</desc>
+<rect class="background" x="{x}" y="{y}" width="128" height="128"/>
+<circle cx="{cx}" cy="{cy}" r="48" fill="#f5a9b8"/>
+<path d="M{x} {y} L{cx} {cy} L{x2} {y}" fill="none" stroke="#5bcefa" stroke-width="8"/>
+</g>"##,
+                index = index,
+                x = x,
+                y = y,
+                cx = x + 64,
+                cy = y + 64,
+                x2 = x + 128,
+            )
+        })
+        .collect();
+    let svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}"><style></style>{}</svg>"#,
+        groups,
+        w = columns * 128,
+        h = (count / columns + 1) * 128,
+    );
+
+    Document::new(
+        vec![("bench.svg".into(), svg)],
+        NamePolicy::Reject,
+        &[MetadataSource::Desc],
+    )
+    .unwrap()
+}
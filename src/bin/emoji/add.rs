@@ -0,0 +1,117 @@
+use emoji_crafter::prelude::*;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Command {
+    /// Name of the new emoji
+    name: String,
+    /// Name of the project
+    #[structopt(default_value = "./")]
+    #[structopt(parse(try_from_str = ProjectPath::validate))]
+    path: PathBuf,
+    /// Add an animation instead of an image
+    #[structopt(long)]
+    animation: bool,
+    /// Number of frames in the animation, 2 by default
+    #[structopt(long)]
+    frames: Option<usize>,
+    /// Delay of each frame in ms, 100 by default
+    #[structopt(long)]
+    delay: Option<usize>,
+    /// Document file to add the emoji to, when the emojiset has more than one
+    #[structopt(long)]
+    document: Option<PathBuf>,
+}
+
+impl Command {
+    pub fn run(self) {
+        let path = self.path.clone();
+
+        let data = std::fs::read_to_string(path.join("emoji.toml"))
+            .expect("error reading emoji.toml, does the file exist?");
+        let mut project = toml::from_str::<Project>(&data)
+            .expect("error reading emoji.toml, there may be a syntax error");
+
+        project.path = path;
+
+        if let Err(message) = self.add(&project) {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    }
+
+    fn add(&self, project: &Project) -> Result<(), String> {
+        let document = Document::from(project);
+        let file = target(project, &document, self.document.as_ref())?;
+
+        let mut scaffold = Scaffold::new(&document, file, &project.emojiset.metadata)
+            .map_err(|e| e.to_string())?;
+        let placement = if self.animation || self.frames.is_some() || self.delay.is_some() {
+            let delays = vec![self.delay.unwrap_or(100); self.frames.unwrap_or(2).max(1)];
+
+            scaffold.animation(&self.name, &delays, |_, placement| background(placement))
+        } else {
+            scaffold.image(&self.name, background)
+        }
+        .map_err(|e| e.to_string())?;
+
+        let path = &document.files[file].path;
+
+        std::fs::write(project.path.join(path), scaffold.finish())
+            .expect("error writing the emojiset document");
+
+        println!(
+            "Added {} to {} at {}, {}",
+            self.name,
+            path.display(),
+            placement.x,
+            placement.y
+        );
+
+        Ok(())
+    }
+}
+
+/// Index of the document file to add emoji to, which must be chosen when
+/// the emojiset has more than one
+pub fn target(
+    project: &Project,
+    document: &Document,
+    choice: Option<&PathBuf>,
+) -> Result<usize, String> {
+    if project.emojiset.layout == Layout::Files {
+        return Err(
+            "emoji can only be added to a document, with the files layout add a new svg file instead"
+                .into(),
+        );
+    }
+
+    match (choice, document.files.len()) {
+        (None, 1) => Ok(0),
+        (None, _) => Err(format!(
+            "the emojiset has more than one file, choose one with --document:\n{}",
+            document
+                .files
+                .iter()
+                .map(|file| format!("  {}", file.path.display()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )),
+        (Some(path), _) => document
+            .files
+            .iter()
+            .position(|file| &file.path == path)
+            .ok_or_else(|| format!("{} isn't a file of the emojiset", path.display())),
+    }
+}
+
+/// A background rect that fills the cell, so exports are the size of the grid
+pub fn background(placement: Placement) -> String {
+    format!(
+        r#"<rect class="background" width="{size}" height="{size}" x="{}" y="{}" />"#,
+        placement.x,
+        placement.y,
+        size = placement.size
+    )
+}
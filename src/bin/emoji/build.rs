@@ -1,6 +1,12 @@
 use emoji_crafter::prelude::*;
+use glob::Pattern;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use std::path::PathBuf;
+use rayon::prelude::*;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use structopt::StructOpt;
 
@@ -10,36 +16,365 @@ pub struct Command {
     #[structopt(default_value = "./")]
     #[structopt(parse(try_from_str = ProjectPath::validate))]
     pub(crate) path: PathBuf,
+    /// Render every emoji instead of reusing unchanged ones from the cache
+    #[structopt(long)]
+    pub(crate) no_cache: bool,
+    /// Number of emoji to render at once, defaults to the number of cores
+    #[structopt(long, short, default_value = "0")]
+    pub(crate) jobs: usize,
+    /// Only build emoji with a name or alias matching one of these globs
+    #[structopt(long = "emoji")]
+    pub(crate) emojis: Vec<Pattern>,
+    /// Only build these themes
+    #[structopt(long = "theme")]
+    pub(crate) themes: Vec<String>,
+    /// Only build the outputs with these directories
+    #[structopt(long = "output")]
+    pub(crate) outputs: Vec<PathBuf>,
+    /// List the files that would be written, other than the cache, without rendering anything
+    #[structopt(long)]
+    pub(crate) dry_run: bool,
+    /// Remove files that earlier builds wrote but the project no longer exports
+    #[structopt(long)]
+    pub(crate) prune: bool,
+}
+
+/// Limits a build to parts of the project, everything is built when a field is `None`
+#[derive(Clone, Debug, Default)]
+pub struct Scope {
+    /// Names of the themes to render
+    pub themes: Option<HashSet<String>>,
+    /// Ids of the emoji to render
+    pub emojis: Option<HashSet<String>>,
+    /// Inputs of the templates to render
+    pub templates: Option<HashSet<PathBuf>>,
+    /// Directories of the outputs to render
+    pub outputs: Option<HashSet<PathBuf>>,
+    /// Directories of the galleries to render
+    pub galleries: Option<HashSet<PathBuf>>,
+}
+
+impl Scope {
+    fn has_theme(&self, theme: &Theme) -> bool {
+        match &self.themes {
+            Some(themes) => themes.contains(&theme.name),
+            None => true,
+        }
+    }
+
+    fn has_emoji(&self, emoji: &Emoji) -> bool {
+        match &self.emojis {
+            Some(emojis) => emojis.contains(&emoji.id().unwrap()),
+            None => true,
+        }
+    }
+
+    fn has_output(&self, output: &Output) -> bool {
+        match &self.outputs {
+            Some(outputs) => outputs.contains(&normalize(&output.directory)),
+            None => true,
+        }
+    }
+
+    /// Whether every emoji of every theme is being built
+    fn has_all_emoji(&self) -> bool {
+        self.themes.is_none() && self.emojis.is_none() && self.outputs.is_none()
+    }
+
+    fn has_template(&self, template: &Template) -> bool {
+        match &self.templates {
+            Some(templates) => templates.contains(&template.input),
+            None => true,
+        }
+    }
+
+    fn has_gallery(&self, gallery: &Gallery) -> bool {
+        match &self.galleries {
+            Some(galleries) => galleries.contains(&gallery.directory),
+            None => true,
+        }
+    }
+}
+
+/// Drop `.` components, so `./original` and `original/` name the same directory
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| component != &Component::CurDir)
+        .collect()
 }
 
 impl Command {
     pub fn run(self) {
-        let path = self.path;
+        let path = self.path.clone();
 
-        let data = std::fs::read_to_string(&path.join("emoji.toml"))
+        let data = std::fs::read_to_string(path.join("emoji.toml"))
             .expect("error reading emoji.toml, does the file exist?");
         let mut project = toml::from_str::<Project>(&data)
             .expect("error reading emoji.toml, there may be a syntax error");
 
         project.path = path;
 
+        let document = Document::from(&project);
+        let scope = match self.scope(&project, &document) {
+            Ok(scope) => scope,
+            Err(message) => {
+                eprintln!("{}", message);
+                std::process::exit(1);
+            }
+        };
+
+        if self.dry_run {
+            return self.dry_run(&project, &document, &scope);
+        }
+
+        if self.build(project, document, &scope).is_err() {
+            std::process::exit(1);
+        }
+    }
+
+    /// Print the path, format, size and frame count of every file the build
+    /// would write, other than cache entries. Trimmed outputs are at most the
+    /// size shown.
+    fn dry_run(&self, project: &Project, document: &Document, scope: &Scope) {
+        let all: Vec<_> = document
+            .emojis
+            .iter()
+            .map(|(_, emoji)| emoji.clone())
+            .collect();
+
+        project
+            .check_paths(&all)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let emojis: Vec<_> = all
+            .into_iter()
+            .filter(|emoji| scope.has_emoji(emoji))
+            .collect();
+        let sizes = emoji_renderer::measure(document, &emojis);
+        let mut rows = vec![["format", "size", "frames", "path"].map(String::from)];
+
+        for theme in project.themes.iter().filter(|theme| scope.has_theme(theme)) {
+            for output in project
+                .outputs
+                .iter()
+                .filter(|output| scope.has_output(output))
+            {
+                for (emoji, &(width, height)) in emojis.iter().zip(&sizes) {
+                    let (width, height) = match output.size {
+                        Some(size) => emoji_renderer::fit(width, height, size),
+                        None => (width, height),
+                    };
+                    let vector = output.format == OutputFormat::Svg;
+                    let size = if output.trim && !vector {
+                        format!("<={}x{}", width, height)
+                    } else {
+                        format!("{}x{}", width, height)
+                    };
+                    // Svg animations have a file for each frame:
+                    let frames = if vector {
+                        1
+                    } else {
+                        emoji.frames().len().max(1)
+                    }
+                    .to_string();
+
+                    for name in emoji.names() {
+                        for extension in output.extensions(emoji) {
+                            rows.push([
+                                extension.rsplit('.').next().unwrap().to_string(),
+                                size.clone(),
+                                frames.clone(),
+                                output
+                                    .path(theme, emoji, &name, &extension)
+                                    .display()
+                                    .to_string(),
+                            ]);
+                        }
+                    }
+                }
+            }
+        }
+
+        let file = |format: &str, size: String, path: PathBuf| {
+            [format.into(), size, "-".into(), path.display().to_string()]
+        };
+
+        for template in project
+            .templates
+            .iter()
+            .filter(|template| scope.has_template(template))
+        {
+            let format = template
+                .output
+                .extension()
+                .map_or("text".into(), |extension| extension.to_string_lossy());
+
+            rows.push(file(&format, "-".into(), template.output.clone()));
+        }
+
+        for gallery in project
+            .galleries
+            .iter()
+            .filter(|gallery| scope.has_gallery(gallery))
+        {
+            rows.push(file("html", "-".into(), gallery_renderer::path(gallery)));
+        }
+
+        // Previews show every emoji, even when only some are being built:
+        for theme in project.themes.iter().filter(|theme| scope.has_theme(theme)) {
+            for preview in &project.previews {
+                let (width, height) = preview_renderer::size(document.emojis.len(), preview);
+                let format = if preview.animated { "webp" } else { "png" };
+
+                for path in preview_renderer::paths(&theme.name, preview) {
+                    rows.push(file(format, format!("{}x{}", width, height), path));
+                }
+            }
+        }
+
+        for output in project
+            .outputs
+            .iter()
+            .filter(|output| scope.has_output(output))
+        {
+            rows.push(file(
+                "toml",
+                "-".into(),
+                output.directory.join(BuildManifest::FILE_NAME),
+            ));
+        }
+
+        let widths: Vec<_> = (0..3)
+            .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap())
+            .collect();
+
+        for [format, size, frames, path] in &rows {
+            println!(
+                "{: <4$}  {: <5$}  {: <6$}  {}",
+                format, size, frames, path, widths[0], widths[1], widths[2]
+            );
+        }
+    }
+
+    /// Limit the build to the emoji, themes and outputs given on the command line
+    fn scope(&self, project: &Project, document: &Document) -> Result<Scope, String> {
+        let mut scope = Scope::default();
+
+        if !self.emojis.is_empty() {
+            let ids: HashSet<_> = document
+                .emojis
+                .iter()
+                .filter(|(_, emoji)| {
+                    emoji
+                        .names()
+                        .iter()
+                        .any(|name| self.emojis.iter().any(|pattern| pattern.matches(name)))
+                })
+                .map(|(id, _)| id.clone())
+                .collect();
+
+            if ids.is_empty() {
+                return Err("no emoji match the --emoji patterns".into());
+            }
+
+            scope.emojis = Some(ids);
+        }
+
+        if !self.themes.is_empty() {
+            for name in &self.themes {
+                if !project.themes.iter().any(|theme| &theme.name == name) {
+                    return Err(format!("there is no theme named {}", name));
+                }
+            }
+
+            scope.themes = Some(self.themes.iter().cloned().collect());
+        }
+
+        if !self.outputs.is_empty() {
+            for directory in &self.outputs {
+                if !project
+                    .outputs
+                    .iter()
+                    .any(|output| normalize(&output.directory) == normalize(directory))
+                {
+                    return Err(format!(
+                        "there is no output with the directory {}",
+                        directory.display()
+                    ));
+                }
+            }
+
+            scope.outputs = Some(self.outputs.iter().map(|path| normalize(path)).collect());
+        }
+
+        // Templates and galleries list every emoji, so a partial build leaves them be:
+        if scope.emojis.is_some() || scope.themes.is_some() || scope.outputs.is_some() {
+            scope.templates = Some(HashSet::new());
+            scope.galleries = Some(HashSet::new());
+        }
+
+        Ok(scope)
+    }
+
+    /// Build the parts of the project that are in scope, returning the error
+    /// message if any part of the build failed
+    pub fn build(&self, project: Project, document: Document, scope: &Scope) -> Result<(), String> {
         println!("Building {} emojiset...\n", project.emojiset.name);
 
-        let document = Document::from(&project);
+        for renamed in &document.renamed {
+            println!(
+                "Renamed \"{}\" in \"{}\" to \"{}\" so it can be used as a filename",
+                renamed.from, renamed.id, renamed.to
+            );
+        }
+
         let emojis: Vec<_> = document
             .emojis
             .iter()
             .map(|(_, emoji)| emoji.clone())
             .collect();
 
+        project
+            .check_paths(&emojis)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let selected: Vec<_> = emojis
+            .iter()
+            .filter(|emoji| scope.has_emoji(emoji))
+            .cloned()
+            .collect();
+        let themes: Vec<_> = project
+            .themes
+            .iter()
+            .filter(|theme| scope.has_theme(theme))
+            .cloned()
+            .collect();
+        let templates: Vec<_> = project
+            .templates
+            .iter()
+            .filter(|template| scope.has_template(template))
+            .cloned()
+            .collect();
+        let outputs: Vec<_> = project
+            .outputs
+            .iter()
+            .filter(|output| scope.has_output(output))
+            .map(|output| {
+                let mut output = output.clone();
+
+                output.directory = project.path.join(output.directory.clone());
+                output
+            })
+            .collect();
+
         let bars = MultiProgress::new();
         let bar_characters = "▓▒░";
         let mut threads = Vec::new();
 
-        if !project.templates.is_empty() {
+        if !templates.is_empty() {
             let project = project.clone();
             let emojis = emojis.clone();
-            let template_bar = bars.add(ProgressBar::new(project.templates.len() as u64));
+            let template_bar = bars.add(ProgressBar::new(templates.len() as u64));
 
             template_bar.set_style(
                 ProgressStyle::default_bar()
@@ -53,28 +388,41 @@ impl Command {
             threads.push(thread::spawn(move || {
                 let renderable = template_renderer::process(&project, &emojis);
 
-                template_renderer::render(
-                    &renderable,
-                    &project.templates,
-                    |template: &Template| {
-                        template_bar.set_message(
-                            template
-                                .output
-                                .file_name()
-                                .unwrap()
-                                .to_str()
-                                .unwrap()
-                                .to_string(),
-                        );
-                        template_bar.inc(1);
-                    },
-                );
+                template_renderer::render(&renderable, &templates, |template: &Template| {
+                    template_bar.set_message(
+                        template
+                            .output
+                            .file_name()
+                            .unwrap()
+                            .to_str()
+                            .unwrap()
+                            .to_string(),
+                    );
+                    template_bar.inc(1);
+                });
 
                 template_bar.finish_with_message("done");
             }));
         }
 
-        let theme_bar = bars.add(ProgressBar::new(project.themes.len() as u64));
+        let galleries: Vec<_> = project
+            .galleries
+            .iter()
+            .filter(|gallery| scope.has_gallery(gallery))
+            .cloned()
+            .collect();
+
+        for gallery in &galleries {
+            let mut gallery = gallery.clone();
+
+            gallery.directory = project.path.join(gallery.directory.clone());
+
+            let renderable = gallery_renderer::process(&project, &emojis, &gallery);
+
+            gallery_renderer::render(&renderable, &gallery);
+        }
+
+        let theme_bar = bars.add(ProgressBar::new(themes.len() as u64));
 
         theme_bar.set_style(
             ProgressStyle::default_bar()
@@ -86,7 +434,7 @@ impl Command {
         );
 
         let emoji_bar = bars.add(ProgressBar::new(
-            (emojis.len() * 2 * project.themes.len()) as u64,
+            (selected.len() * outputs.len() * themes.len()) as u64,
         ));
 
         emoji_bar.set_style(
@@ -98,40 +446,303 @@ impl Command {
                 .progress_chars(bar_characters),
         );
 
-        threads.push(thread::spawn(move || {
-            theme_bar.set_position(0);
-            emoji_bar.set_position(0);
+        let scope = scope.clone();
+        let cache = if self.no_cache {
+            None
+        } else {
+            Some(Cache::new(project.path.join(".emoji-cache")))
+        };
+        let reused = Arc::new(AtomicUsize::new(0));
+        let total = selected.len() * themes.len();
+        let jobs = self.jobs;
+        let project_path = project.path.clone();
+        let written = BuildManifest::tracked(
+            &project,
+            &themes,
+            &selected,
+            &project
+                .outputs
+                .iter()
+                .filter(|output| scope.has_output(output))
+                .cloned()
+                .collect::<Vec<_>>(),
+            &galleries,
+        );
+        let mut expected = BuildManifest::tracked(
+            &project,
+            &project.themes,
+            &emojis,
+            &project.outputs,
+            &project.galleries,
+        );
 
-            for theme in &project.themes {
-                theme_bar.set_message(theme.name.clone());
-                theme_bar.inc(1);
+        {
+            let cache = cache.clone();
+            let reused = reused.clone();
 
-                let mut theme = theme.clone();
+            threads.push(thread::spawn(move || {
+                let keys = Mutex::new(HashSet::new());
+                let sources = Cache::sources(&document, &selected);
+                // Untrimmed, full size files are closest to what previews render:
+                let mut exported: Vec<_> = project
+                    .outputs
+                    .iter()
+                    .map(|output| {
+                        let mut output = output.clone();
 
-                theme.stylesheet = project.path.join(theme.stylesheet.clone());
+                        output.directory = project.path.join(output.directory.clone());
+                        output
+                    })
+                    .collect();
 
-                let renderable = emoji_renderer::process(&document.svg, &theme, &emojis);
+                exported.sort_by_key(|output| (output.trim, output.size.is_some()));
+                let indices: HashMap<_, _> = selected
+                    .iter()
+                    .enumerate()
+                    .map(|(index, emoji)| (emoji.id().unwrap(), index))
+                    .collect();
 
-                for output in &project.outputs {
-                    let mut output = output.clone();
+                // Emoji finish in whatever order the workers get to them, but are
+                // reported theme by theme in the order of the document:
+                let progress = Progress::new(themes.len() * selected.len());
+                let finish = |theme: usize, emoji: usize| {
+                    progress.finish(theme * selected.len() + emoji, |index| {
+                        let (theme, emoji) = (index / selected.len(), index % selected.len());
 
-                    output.directory = project.path.join(output.directory.clone());
+                        emoji_bar.set_message(selected[emoji].name().unwrap());
+                        emoji_bar.inc(outputs.len() as u64);
 
-                    emoji_renderer::render(&renderable, &theme, &output, |emoji: &Emoji| {
-                        emoji_bar.set_message(emoji.name().unwrap());
-                        emoji_bar.inc(1);
+                        if emoji + 1 == selected.len() {
+                            theme_bar.set_message(themes[theme].name.clone());
+                            theme_bar.inc(1);
+                        }
                     });
+                };
+
+                theme_bar.set_position(0);
+                emoji_bar.set_position(0);
+
+                // Every theme, output and emoji shares one pool. Each worker holds
+                // one rasterized emoji at a time, so the number of jobs also limits
+                // how much memory a build uses:
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(jobs)
+                    .build()
+                    .expect("error starting render threads");
+
+                pool.install(|| {
+                    themes
+                        .par_iter()
+                        .enumerate()
+                        .for_each(|(theme_index, theme)| {
+                            let mut theme = theme.clone();
+
+                            theme.stylesheet = project.path.join(theme.stylesheet.clone());
+
+                            let css = std::fs::read_to_string(&theme.stylesheet).unwrap();
+
+                            // Restore unchanged emoji from the cache, render the rest:
+                            let mut pending = HashMap::new();
+
+                            for (index, emoji) in selected.iter().enumerate() {
+                                let emoji_keys: Vec<_> = outputs
+                                    .iter()
+                                    .map(|output| {
+                                        Cache::key(
+                                            &sources[&emoji.id().unwrap()],
+                                            &theme,
+                                            &css,
+                                            output,
+                                        )
+                                    })
+                                    .collect();
+                                let restored = cache.as_ref().is_some_and(|cache| {
+                                    outputs.iter().zip(&emoji_keys).all(|(output, key)| {
+                                        cache.restore(key, emoji, &theme, output)
+                                    })
+                                });
+
+                                keys.lock().unwrap().extend(emoji_keys.iter().cloned());
+
+                                if restored {
+                                    reused.fetch_add(1, Ordering::SeqCst);
+                                    finish(theme_index, index);
+                                } else {
+                                    pending.insert(emoji.id().unwrap(), emoji_keys);
+                                }
+                            }
+
+                            let changed: Vec<_> = selected
+                                .iter()
+                                .filter(|emoji| pending.contains_key(&emoji.id().unwrap()))
+                                .cloned()
+                                .collect();
+                            let sheets = Mutex::new(
+                                project
+                                    .previews
+                                    .iter()
+                                    .map(|preview| {
+                                        let mut preview = preview.clone();
+
+                                        preview.directory =
+                                            project.path.join(preview.directory.clone());
+
+                                        preview_renderer::ContactSheet::new(
+                                            &emojis, &theme, &preview,
+                                        )
+                                    })
+                                    .collect::<Vec<_>>(),
+                            );
+                            let add_to_sheets = |renderable: &emoji_renderer::RenderableEmoji| {
+                                for sheet in sheets.lock().unwrap().iter_mut() {
+                                    sheet.add(renderable);
+                                }
+                            };
+
+                            emoji_renderer::render(
+                                &document,
+                                &theme,
+                                &changed,
+                                &outputs,
+                                !project.previews.is_empty(),
+                                |emoji: &Emoji, renderable| {
+                                    let id = emoji.id().unwrap();
+
+                                    if let Some(cache) = &cache {
+                                        for (output, key) in outputs.iter().zip(&pending[&id]) {
+                                            cache.store(key, emoji, &theme, output);
+                                        }
+                                    }
+
+                                    if let Some(renderable) = renderable {
+                                        add_to_sheets(renderable);
+                                    }
+
+                                    finish(theme_index, indices[&id]);
+                                },
+                            );
+
+                            // Previews show every emoji, even when only some are being built.
+                            // Emoji that weren't rendered are read back from their exported
+                            // files, and only rendered when no output has them:
+                            if !project.previews.is_empty() {
+                                let unchanged: Vec<_> = emojis
+                                    .par_iter()
+                                    .filter(|emoji| !pending.contains_key(&emoji.id().unwrap()))
+                                    .filter(|emoji| {
+                                        match emoji_renderer::load(emoji, &theme, &exported) {
+                                            Some(renderable) => {
+                                                add_to_sheets(&renderable);
+                                                false
+                                            }
+                                            None => true,
+                                        }
+                                    })
+                                    .cloned()
+                                    .collect();
+
+                                emoji_renderer::render(
+                                    &document,
+                                    &theme,
+                                    &unchanged,
+                                    &[],
+                                    true,
+                                    |_: &Emoji, renderable| {
+                                        if let Some(renderable) = renderable {
+                                            add_to_sheets(renderable);
+                                        }
+                                    },
+                                );
+                            }
+
+                            for sheet in sheets.into_inner().unwrap() {
+                                sheet.save();
+                            }
+                        });
+                });
+
+                if let Some(cache) = &cache {
+                    if scope.has_all_emoji() {
+                        cache.prune(&keys.into_inner().unwrap());
+                    }
                 }
-            }
 
-            emoji_bar.finish_with_message("done");
-            theme_bar.finish_with_message("done");
-        }));
+                emoji_bar.finish_with_message("done");
+                theme_bar.finish_with_message("done");
+            }));
+        }
 
         bars.join().unwrap();
 
         for thread in threads {
-            let _ = thread.join();
+            thread.join().map_err(panic_message)?;
+        }
+
+        // Keep track of what was written, so stale files can be found later:
+        for (directory, written) in written {
+            let mut manifest = BuildManifest::load(&directory);
+
+            manifest.files.extend(written);
+
+            if self.prune {
+                let expected = expected.remove(&directory).unwrap_or_default();
+
+                for path in manifest.prune(&directory, &expected) {
+                    println!(
+                        "Removed {}",
+                        path.strip_prefix(&project_path).unwrap_or(&path).display()
+                    );
+                }
+            }
+
+            manifest.save(&directory);
+        }
+
+        if cache.is_some() && total > 0 {
+            println!(
+                "\nReused {} of {} emoji from the cache",
+                reused.load(Ordering::SeqCst),
+                total
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Tracks which items of a build have finished, so they can be reported in order
+struct Progress {
+    state: Mutex<(Vec<bool>, usize)>,
+}
+
+impl Progress {
+    fn new(len: usize) -> Self {
+        Self {
+            state: Mutex::new((vec![false; len], 0)),
+        }
+    }
+
+    /// Mark an item as finished and report every item up to the first one
+    /// that is still unfinished
+    fn finish<F: FnMut(usize)>(&self, index: usize, mut report: F) {
+        let mut state = self.state.lock().unwrap();
+        let (finished, next) = &mut *state;
+
+        finished[index] = true;
+
+        while *next < finished.len() && finished[*next] {
+            report(*next);
+            *next += 1;
         }
     }
 }
+
+pub fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown error".into()
+    }
+}
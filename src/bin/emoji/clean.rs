@@ -0,0 +1,65 @@
+use emoji_crafter::prelude::*;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Command {
+    /// Name of the project
+    #[structopt(default_value = "./")]
+    #[structopt(parse(try_from_str = ProjectPath::validate))]
+    path: PathBuf,
+    /// Remove every file that builds have written, not only stale ones
+    #[structopt(long)]
+    all: bool,
+}
+
+impl Command {
+    pub fn run(self) {
+        let path = self.path.clone();
+
+        let data = std::fs::read_to_string(path.join("emoji.toml"))
+            .expect("error reading emoji.toml, does the file exist?");
+        let mut project = toml::from_str::<Project>(&data)
+            .expect("error reading emoji.toml, there may be a syntax error");
+
+        project.path = path;
+
+        let document = Document::from(&project);
+        let emojis: Vec<_> = document
+            .emojis
+            .iter()
+            .map(|(_, emoji)| emoji.clone())
+            .collect();
+        let expected = BuildManifest::tracked(
+            &project,
+            &project.themes,
+            &emojis,
+            &project.outputs,
+            &project.galleries,
+        );
+        let mut removed = 0;
+
+        for (directory, files) in &expected {
+            let mut manifest = BuildManifest::load(directory);
+            let expected = if self.all {
+                Default::default()
+            } else {
+                files.clone()
+            };
+
+            for path in manifest.prune(directory, &expected) {
+                println!(
+                    "Removed {}",
+                    path.strip_prefix(&project.path).unwrap_or(&path).display()
+                );
+                removed += 1;
+            }
+
+            manifest.save(directory);
+        }
+
+        if removed == 0 {
+            println!("Nothing to clean");
+        }
+    }
+}
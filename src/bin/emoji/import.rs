@@ -0,0 +1,100 @@
+use crate::add::{background, target};
+use emoji_crafter::prelude::*;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Command {
+    /// Png, svg, gif or webp files to import, emoji are named after the files
+    #[structopt(required = true)]
+    files: Vec<PathBuf>,
+    /// Name of the project
+    #[structopt(long = "project", default_value = "./")]
+    #[structopt(parse(try_from_str = ProjectPath::validate))]
+    path: PathBuf,
+    /// Document file to add the emoji to, when the emojiset has more than one
+    #[structopt(long)]
+    document: Option<PathBuf>,
+}
+
+impl Command {
+    pub fn run(self) {
+        let path = self.path.clone();
+
+        let data = std::fs::read_to_string(path.join("emoji.toml"))
+            .expect("error reading emoji.toml, does the file exist?");
+        let mut project = toml::from_str::<Project>(&data)
+            .expect("error reading emoji.toml, there may be a syntax error");
+
+        project.path = path;
+
+        if let Err(message) = self.import(&project) {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    }
+
+    fn import(&self, project: &Project) -> Result<(), String> {
+        let document = Document::from(project);
+        let file = target(project, &document, self.document.as_ref())?;
+        let mut scaffold = Scaffold::new(&document, file, &project.emojiset.metadata)
+            .map_err(|e| e.to_string())?;
+        let href = scaffold.href();
+        let mut imported = 0;
+
+        for path in &self.files {
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let added = Import::read(path)
+                .map_err(|e| e.to_string())
+                .and_then(|import| {
+                    let size = import.size();
+
+                    match import {
+                        Import::Image { mime, data, .. } => scaffold.image(&name, |placement| {
+                            format!(
+                                "{}\n    {}",
+                                background(placement),
+                                embed(mime, &data, size, placement, &href)
+                            )
+                        }),
+                        Import::Animation { frames, .. } => {
+                            let delays: Vec<_> = frames.iter().map(|(_, delay)| *delay).collect();
+
+                            scaffold.animation(&name, &delays, |index, placement| {
+                                format!(
+                                    "{}\n      {}",
+                                    background(placement),
+                                    embed("image/png", &frames[index].0, size, placement, &href)
+                                )
+                            })
+                        }
+                    }
+                    .map_err(|e| e.to_string())
+                });
+
+            match added {
+                Ok(placement) => {
+                    println!("Added {} at {}, {}", name, placement.x, placement.y);
+                    imported += 1;
+                }
+                Err(error) => println!("Skipped {}: {}", path.display(), error),
+            }
+        }
+
+        if imported == 0 {
+            return Err("No emoji were imported".into());
+        }
+
+        let path = &document.files[file].path;
+
+        std::fs::write(project.path.join(path), scaffold.finish())
+            .expect("error writing the emojiset document");
+
+        println!("Imported {} emoji into {}", imported, path.display());
+
+        Ok(())
+    }
+}
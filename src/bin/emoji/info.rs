@@ -1,5 +1,8 @@
 use emoji_crafter::prelude::*;
+use glob::Pattern;
+use serde::Serialize;
 use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -8,13 +11,103 @@ pub struct Command {
     #[structopt(default_value = "./")]
     #[structopt(parse(try_from_str = ProjectPath::validate))]
     path: PathBuf,
+    /// Output format, one of toml, json, table or csv
+    #[structopt(short, long, default_value = "toml")]
+    format: Format,
+    /// Only list emoji of this type, either image or animation
+    #[structopt(long = "type")]
+    kind: Option<Kind>,
+    /// Only list emoji with a name or alias matching this glob
+    #[structopt(short, long)]
+    name: Option<Pattern>,
+    /// Only list emoji with one of these tags
+    #[structopt(long = "tag")]
+    tags: Vec<String>,
+    /// Only list output paths for these themes
+    #[structopt(long = "theme")]
+    themes: Vec<String>,
+}
+
+#[derive(Debug)]
+enum Format {
+    Toml,
+    Json,
+    Table,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "toml" => Ok(Format::Toml),
+            "json" => Ok(Format::Json),
+            "table" => Ok(Format::Table),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!(
+                "unknown format {}, use toml, json, table or csv",
+                format
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Image,
+    Animation,
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "image" | "static" => Ok(Kind::Image),
+            "animation" | "animated" => Ok(Kind::Animation),
+            _ => Err(format!("unknown type {}, use image or animation", kind)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Info {
+    emoji: Vec<EmojiInfo>,
+}
+
+#[derive(Serialize)]
+struct EmojiInfo {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+    aliases: Vec<String>,
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    width: usize,
+    height: usize,
+    frames: usize,
+    duration: usize,
+    /// Svg file the emoji is defined in
+    file: PathBuf,
+    outputs: Vec<OutputInfo>,
+}
+
+#[derive(Serialize)]
+struct OutputInfo {
+    theme: String,
+    directory: PathBuf,
+    names: Vec<String>,
+    paths: Vec<PathBuf>,
 }
 
 impl Command {
     pub fn run(self) {
-        let path = self.path;
+        let path = self.path.clone();
 
-        let data = std::fs::read_to_string(&path.join("emoji.toml"))
+        let data = std::fs::read_to_string(path.join("emoji.toml"))
             .expect("error reading emoji.toml, does the file exist?");
         let mut project = toml::from_str::<Project>(&data)
             .expect("error reading emoji.toml, there may be a syntax error");
@@ -22,7 +115,172 @@ impl Command {
         project.path = path;
 
         let document = Document::from(&project);
+        let emojis: Vec<_> = document
+            .emojis
+            .iter()
+            .map(|(_, emoji)| emoji.clone())
+            .filter(|emoji| self.matches(emoji))
+            .collect();
+        let sizes = emoji_renderer::measure(&document, &emojis);
+
+        let info = Info {
+            emoji: emojis
+                .iter()
+                .zip(sizes)
+                .map(|(emoji, (width, height))| EmojiInfo {
+                    id: emoji.id().unwrap(),
+                    kind: if emoji.is_animation() {
+                        "animation".into()
+                    } else {
+                        "image".into()
+                    },
+                    name: emoji.name().unwrap(),
+                    aliases: emoji.aliases(),
+                    tags: emoji.tags(),
+                    category: emoji.category(),
+                    width,
+                    height,
+                    frames: emoji.frames().len(),
+                    duration: emoji.duration(),
+                    file: document.file(&emoji.id().unwrap()).path.clone(),
+                    outputs: self.outputs(&project, emoji),
+                })
+                .collect(),
+        };
+
+        match self.format {
+            Format::Toml => println!("{}", toml::to_string_pretty(&info).unwrap()),
+            Format::Json => println!("{}", serde_json::to_string_pretty(&info).unwrap()),
+            Format::Table => print_table(&info),
+            Format::Csv => print_csv(&info),
+        }
+    }
+
+    fn matches(&self, emoji: &Emoji) -> bool {
+        let kind = match &self.kind {
+            Some(Kind::Image) => emoji.is_image(),
+            Some(Kind::Animation) => emoji.is_animation(),
+            None => true,
+        };
+        let name = match &self.name {
+            Some(pattern) => emoji.names().iter().any(|name| pattern.matches(name)),
+            None => true,
+        };
+        let tags = self.tags.is_empty() || emoji.tags().iter().any(|tag| self.tags.contains(tag));
+
+        kind && name && tags
+    }
+
+    fn outputs(&self, project: &Project, emoji: &Emoji) -> Vec<OutputInfo> {
+        let mut outputs = Vec::new();
+
+        for theme in &project.themes {
+            if !self.themes.is_empty() && !self.themes.contains(&theme.name) {
+                continue;
+            }
+
+            for output in &project.outputs {
+                let paths = emoji
+                    .names()
+                    .iter()
+                    .flat_map(|name| {
+                        output
+                            .extensions(emoji)
+                            .into_iter()
+                            .map(move |extension| output.path(theme, emoji, name, &extension))
+                    })
+                    .collect();
+
+                outputs.push(OutputInfo {
+                    theme: theme.name.clone(),
+                    directory: output.directory.clone(),
+                    names: emoji
+                        .names()
+                        .iter()
+                        .map(|name| output.name(theme, name))
+                        .collect(),
+                    paths,
+                });
+            }
+        }
+
+        outputs
+    }
+}
+
+const COLUMNS: [&str; 10] = [
+    "id", "type", "name", "aliases", "tags", "size", "frames", "duration", "file", "paths",
+];
+
+fn rows(info: &Info) -> Vec<Vec<String>> {
+    info.emoji
+        .iter()
+        .map(|emoji| {
+            let paths: Vec<_> = emoji
+                .outputs
+                .iter()
+                .flat_map(|output| output.paths.iter())
+                .map(|path| path.display().to_string())
+                .collect();
+
+            vec![
+                emoji.id.clone(),
+                emoji.kind.clone(),
+                emoji.name.clone(),
+                emoji.aliases.join(" "),
+                emoji.tags.join(" "),
+                format!("{}x{}", emoji.width, emoji.height),
+                emoji.frames.to_string(),
+                emoji.duration.to_string(),
+                emoji.file.display().to_string(),
+                paths.join(" "),
+            ]
+        })
+        .collect()
+}
+
+fn print_table(info: &Info) {
+    let rows = rows(info);
+    // Paths are too long to line up, so they're left out of the table
+    let columns = COLUMNS.len() - 1;
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .fold(COLUMNS[column].len(), usize::max)
+        })
+        .collect();
+    let print_row = |row: Vec<&str>| {
+        let cells: Vec<_> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{: <1$}", cell, width))
+            .collect();
+
+        println!("{}", cells.join("  ").trim_end());
+    };
+
+    print_row(COLUMNS[..columns].to_vec());
+
+    for row in &rows {
+        print_row(row[..columns].iter().map(|cell| cell.as_str()).collect());
+    }
+}
+
+fn print_csv(info: &Info) {
+    println!("{}", COLUMNS.join(","));
+
+    for row in rows(info) {
+        let cells: Vec<_> = row.iter().map(|cell| escape_csv(cell)).collect();
+
+        println!("{}", cells.join(","));
+    }
+}
 
-        println!("{}", toml::to_string_pretty(&document.emojis).unwrap());
+fn escape_csv(cell: &str) -> String {
+    if cell.contains([',', '"', '\n']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.into()
     }
 }
@@ -1,6 +1,12 @@
+mod add;
 mod build;
+mod clean;
+mod import;
 mod info;
 mod new;
+mod preview;
+mod rename;
+mod serve;
 mod watch;
 
 use structopt::StructOpt;
@@ -11,10 +17,20 @@ use structopt::StructOpt;
 enum Opt {
     /// Create a new emojiset
     New(new::Command),
+    /// Add a new emoji group to the emojiset document
+    Add(add::Command),
+    /// Add image and animation files to the emojiset document as new emoji
+    Import(import::Command),
     /// Export emoji from the current emojiset
     Build(build::Command),
+    /// Remove exported files the current emojiset no longer produces
+    Clean(clean::Command),
     /// List emoji information from the current emojiset
     Info(info::Command),
+    /// Render a contact sheet of every emoji for each theme
+    Preview(preview::Command),
+    /// Rename an emoji and the files exported for it
+    Rename(rename::Command),
     /// Watch project assets for changes and then rebuild
     Watch(watch::Command),
 }
@@ -22,8 +38,13 @@ enum Opt {
 fn main() {
     match Opt::from_args() {
         Opt::New(cmd) => cmd.run(),
+        Opt::Add(cmd) => cmd.run(),
+        Opt::Import(cmd) => cmd.run(),
         Opt::Build(cmd) => cmd.run(),
+        Opt::Clean(cmd) => cmd.run(),
         Opt::Info(cmd) => cmd.run(),
+        Opt::Preview(cmd) => cmd.run(),
+        Opt::Rename(cmd) => cmd.run(),
         Opt::Watch(cmd) => cmd.run(),
     }
 }
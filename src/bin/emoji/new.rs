@@ -34,22 +34,35 @@ impl Command {
             Output {
                 trim: false,
                 directory: "original".into(),
+                path: None,
+                size: None,
+                names: Default::default(),
+                format: OutputFormat::Raster,
             },
             Output {
                 trim: true,
                 directory: "trimmed".into(),
+                path: None,
+                size: None,
+                names: Default::default(),
+                format: OutputFormat::Raster,
             },
         ];
         let templates = vec![];
         let themes = vec![Theme {
             name: name.clone(),
             prefix: "".into(),
+            suffix: "".into(),
+            names: Default::default(),
             stylesheet: theme_stylesheet,
         }];
         let emojiset = Emojiset {
             name,
-            document: emojiset_document,
+            document: DocumentPath::One(emojiset_document.clone()),
             stylesheet: emojiset_stylesheet,
+            names: NamePolicy::Reject,
+            layout: Layout::Document,
+            metadata: vec![MetadataSource::Desc],
         };
         let project = Project {
             path: path.clone(),
@@ -57,13 +70,15 @@ impl Command {
             templates,
             themes,
             outputs,
+            previews: vec![],
+            galleries: vec![],
         };
 
         let manifest = path.join("emoji.toml");
 
         std::fs::write(&manifest, toml::to_string(&project).unwrap()).unwrap();
         std::fs::write(
-            path.join(&project.emojiset.document),
+            path.join(&emojiset_document),
             include_str!("../../../tpl/emojiset.svg"),
         )
         .unwrap();
@@ -92,6 +107,7 @@ impl Command {
 fn touch(path: &PathBuf) -> std::io::Result<()> {
     match std::fs::OpenOptions::new()
         .create(true)
+        .truncate(false)
         .write(true)
         .open(path)
     {
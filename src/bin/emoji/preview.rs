@@ -0,0 +1,106 @@
+use emoji_crafter::prelude::*;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Command {
+    /// Name of the project
+    #[structopt(default_value = "./")]
+    #[structopt(parse(try_from_str = ProjectPath::validate))]
+    path: PathBuf,
+    /// Only preview these themes
+    #[structopt(long = "theme")]
+    themes: Vec<String>,
+    /// Play animations in an animated webp instead of showing their first frame
+    #[structopt(long)]
+    animated: bool,
+    /// Number of emoji in each row
+    #[structopt(long)]
+    columns: Option<usize>,
+}
+
+impl Command {
+    pub fn run(self) {
+        let path = self.path.clone();
+
+        let data = std::fs::read_to_string(path.join("emoji.toml"))
+            .expect("error reading emoji.toml, does the file exist?");
+        let mut project = toml::from_str::<Project>(&data)
+            .expect("error reading emoji.toml, there may be a syntax error");
+
+        project.path = path;
+
+        // Use the previews from the manifest if there are any:
+        let previews = if project.previews.is_empty() {
+            vec![Preview::default()]
+        } else {
+            project.previews.clone()
+        };
+
+        let document = Document::from(&project);
+        let emojis: Vec<_> = document
+            .emojis
+            .iter()
+            .map(|(_, emoji)| emoji.clone())
+            .collect();
+
+        for theme in &project.themes {
+            if !self.themes.is_empty() && !self.themes.contains(&theme.name) {
+                continue;
+            }
+
+            let mut theme = theme.clone();
+
+            theme.stylesheet = project.path.join(theme.stylesheet.clone());
+
+            let sheets: Vec<_> = previews
+                .iter()
+                .map(|preview| {
+                    let mut preview = preview.clone();
+
+                    preview.directory = project.path.join(preview.directory.clone());
+                    preview.animated |= self.animated;
+                    preview.columns = self.columns.unwrap_or(preview.columns);
+
+                    preview_renderer::ContactSheet::new(&emojis, &theme, &preview)
+                })
+                .collect();
+            let sheets = Mutex::new(sheets);
+
+            // Draw each emoji as soon as it's rendered instead of keeping them all:
+            emoji_renderer::render(
+                &document,
+                &theme,
+                &emojis,
+                &[],
+                true,
+                |_: &Emoji, renderable: Option<&emoji_renderer::RenderableEmoji>| {
+                    if let Some(renderable) = renderable {
+                        for sheet in sheets.lock().unwrap().iter_mut() {
+                            sheet.add(renderable);
+                        }
+                    }
+                },
+            );
+
+            for (sheet, preview) in sheets.into_inner().unwrap().into_iter().zip(&previews) {
+                let directory = project.path.join(&preview.directory);
+                let mut manifest = BuildManifest::load(&directory);
+
+                for path in sheet.save() {
+                    println!(
+                        "Wrote {}",
+                        path.strip_prefix(&project.path).unwrap_or(&path).display()
+                    );
+
+                    manifest
+                        .files
+                        .insert(path.strip_prefix(&directory).unwrap().to_path_buf());
+                }
+
+                manifest.save(&directory);
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use emoji_crafter::editor;
+use emoji_crafter::prelude::*;
+use std::fs::{copy, create_dir_all, rename};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Command {
+    /// Current name or alias of the emoji
+    old: String,
+    /// Name to give it
+    new: String,
+    /// Name of the project
+    #[structopt(default_value = "./")]
+    #[structopt(parse(try_from_str = ProjectPath::validate))]
+    path: PathBuf,
+    /// Keep the old name as an alias, copying exported files instead of moving them
+    #[structopt(long)]
+    alias: bool,
+}
+
+impl Command {
+    pub fn run(self) {
+        let path = self.path.clone();
+
+        let data = std::fs::read_to_string(path.join("emoji.toml"))
+            .expect("error reading emoji.toml, does the file exist?");
+        let mut project = toml::from_str::<Project>(&data)
+            .expect("error reading emoji.toml, there may be a syntax error");
+
+        project.path = path;
+
+        let document = Document::from(&project);
+        let changes = match editor::rename(&project, &document, &self.old, &self.new, self.alias) {
+            Ok(changes) => changes,
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        };
+
+        for change in changes {
+            match change {
+                Change::Write(path, contents) => std::fs::write(project.path.join(path), contents)
+                    .expect("error writing the emojiset document"),
+                Change::Move(from, to) => rename(project.path.join(from), project.path.join(to))
+                    .expect("error moving the emojiset document"),
+            }
+        }
+
+        let old = document.emojis[&document.names[&self.old]].clone();
+        let document = Document::from(&project);
+        let new = document.emojis[&document.names[&self.new]].clone();
+
+        println!("Renamed {} to {}", self.old, self.new);
+
+        self.move_outputs(&project, &old, &new);
+
+        let emojis: Vec<_> = document
+            .emojis
+            .iter()
+            .map(|(_, emoji)| emoji.clone())
+            .collect();
+
+        // Templates and galleries list emoji by name:
+        let renderable = template_renderer::process(&project, &emojis);
+
+        template_renderer::render(&renderable, &project.templates, |_| {});
+
+        for gallery in &project.galleries {
+            let mut gallery = gallery.clone();
+
+            gallery.directory = project.path.join(gallery.directory.clone());
+
+            let renderable = gallery_renderer::process(&project, &emojis, &gallery);
+            let path = gallery_renderer::render(&renderable, &gallery);
+            let mut manifest = BuildManifest::load(&gallery.directory);
+
+            manifest
+                .files
+                .insert(path.strip_prefix(&gallery.directory).unwrap().to_path_buf());
+            manifest.save(&gallery.directory);
+        }
+    }
+
+    /// Move the files exported under the old name for every theme and output,
+    /// or copy them when the old name is kept as an alias
+    fn move_outputs(&self, project: &Project, old: &Emoji, new: &Emoji) {
+        for output in &project.outputs {
+            let mut output = output.clone();
+
+            output.directory = project.path.join(output.directory.clone());
+
+            let mut manifest = BuildManifest::load(&output.directory);
+
+            for theme in &project.themes {
+                for extension in output.extensions(old) {
+                    let from = output.path(theme, old, &self.old, &extension);
+                    let to = output.path(theme, new, &self.new, &extension);
+
+                    if from == to || !from.exists() || to.exists() {
+                        continue;
+                    }
+
+                    if let Some(parent) = to.parent() {
+                        create_dir_all(parent).expect("error creating output directory");
+                    }
+
+                    if self.alias {
+                        copy(&from, &to).expect("error copying exported emoji");
+                    } else {
+                        rename(&from, &to).expect("error moving exported emoji");
+                        manifest
+                            .files
+                            .remove(from.strip_prefix(&output.directory).unwrap());
+                    }
+
+                    manifest
+                        .files
+                        .insert(to.strip_prefix(&output.directory).unwrap().to_path_buf());
+
+                    println!(
+                        "{} {} to {}",
+                        if self.alias { "Copied" } else { "Moved" },
+                        from.strip_prefix(&project.path).unwrap_or(&from).display(),
+                        to.strip_prefix(&project.path).unwrap_or(&to).display()
+                    );
+                }
+            }
+
+            manifest.save(&output.directory);
+        }
+    }
+}
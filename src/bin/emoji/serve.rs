@@ -0,0 +1,203 @@
+use emoji_crafter::prelude::*;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Injected into served pages so they reload after each build
+const RELOAD_SCRIPT: &str =
+    r#"<script>new EventSource("/_reload").onmessage = () => location.reload();</script>"#;
+
+/// Serves the project directory and a generated gallery on localhost
+pub struct Server {
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl Server {
+    pub fn start(path: PathBuf, port: u16) -> Self {
+        let listener =
+            TcpListener::bind(("127.0.0.1", port)).expect("error starting preview server");
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let server = Self {
+            clients: clients.clone(),
+        };
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let path = path.clone();
+                let clients = clients.clone();
+
+                thread::spawn(move || handle(stream, &path, &clients));
+            }
+        });
+
+        server
+    }
+
+    /// Tell every connected browser to reload
+    pub fn reload(&self) {
+        let mut clients = self.clients.lock().unwrap();
+
+        clients.retain(|mut client| {
+            client
+                .write_all(b"data: reload\n\n")
+                .and_then(|_| client.flush())
+                .is_ok()
+        });
+    }
+}
+
+fn handle(stream: TcpStream, path: &Path, clients: &Mutex<Vec<TcpStream>>) {
+    let mut request = String::new();
+    let mut reader = BufReader::new(&stream);
+
+    if reader.read_line(&mut request).is_err() {
+        return;
+    }
+
+    // Skip the rest of the request headers:
+    let mut header = String::new();
+
+    while reader.read_line(&mut header).is_ok() && header.trim() != "" {
+        header.clear();
+    }
+
+    let target = request.split_whitespace().nth(1).unwrap_or("/");
+    let target = decode_url(target.split('?').next().unwrap());
+
+    match target.as_str() {
+        "/_reload" => {
+            let mut stream = stream;
+            let headers = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n";
+
+            if stream.write_all(headers.as_bytes()).is_ok() {
+                clients.lock().unwrap().push(stream);
+            }
+        }
+        "/" => respond(stream, "200 OK", "text/html", &inject(gallery(path))),
+        _ => match resolve(path, &target)
+            .and_then(|file| std::fs::read(&file).ok().map(|data| (file, data)))
+        {
+            Some((file, data)) => {
+                let content_type = content_type(&file);
+
+                if content_type == "text/html" {
+                    respond(stream, "200 OK", content_type, &inject(data));
+                } else {
+                    respond(stream, "200 OK", content_type, &data);
+                }
+            }
+            None => respond(stream, "404 Not Found", "text/plain", b"not found"),
+        },
+    }
+}
+
+fn respond(mut stream: TcpStream, status: &str, content_type: &str, body: &[u8]) {
+    let headers = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+
+    let _ = stream
+        .write_all(headers.as_bytes())
+        .and_then(|_| stream.write_all(body));
+}
+
+/// Render a gallery of the current build linked from the project root
+fn gallery(path: &Path) -> Vec<u8> {
+    let data = std::fs::read_to_string(path.join("emoji.toml"))
+        .expect("error reading emoji.toml, does the file exist?");
+    let mut project = toml::from_str::<Project>(&data)
+        .expect("error reading emoji.toml, there may be a syntax error");
+
+    project.path = path.to_path_buf();
+
+    let document = Document::from(&project);
+    let emojis: Vec<_> = document
+        .emojis
+        .iter()
+        .map(|(_, emoji)| emoji.clone())
+        .collect();
+    let gallery = Gallery {
+        directory: project.path.clone(),
+    };
+
+    gallery_renderer::to_html(&gallery_renderer::process(&project, &emojis, &gallery)).into_bytes()
+}
+
+fn inject(html: Vec<u8>) -> Vec<u8> {
+    let html = String::from_utf8_lossy(&html);
+
+    match html.rfind("</body>") {
+        Some(index) => format!("{}{}{}", &html[..index], RELOAD_SCRIPT, &html[index..]),
+        None => format!("{}{}", html, RELOAD_SCRIPT),
+    }
+    .into_bytes()
+}
+
+/// Find the file for a request, refusing anything outside the project
+fn resolve(path: &Path, target: &str) -> Option<PathBuf> {
+    let relative = Path::new(target.trim_start_matches('/'));
+
+    if relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        let file = path.join(relative);
+        let file = if file.is_dir() {
+            file.join("index.html")
+        } else {
+            file
+        };
+
+        if file.is_file() {
+            return Some(file);
+        }
+    }
+
+    None
+}
+
+fn content_type(file: &Path) -> &'static str {
+    match file.extension().and_then(|extension| extension.to_str()) {
+        Some("html") => "text/html",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("json") => "application/json",
+        Some("toml") | Some("md") | Some("txt") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+fn decode_url(url: &str) -> String {
+    let bytes = url.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into()
+}
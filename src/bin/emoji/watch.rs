@@ -1,7 +1,11 @@
-use crate::build::Command as BuildCommand;
+use crate::build::{panic_message, Command as BuildCommand, Scope};
+use crate::serve::Server;
 use emoji_crafter::prelude::*;
+use indexmap::IndexSet;
 use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
 use std::time::Duration;
 use structopt::StructOpt;
@@ -12,13 +16,28 @@ pub struct Command {
     #[structopt(default_value = "./")]
     #[structopt(parse(try_from_str = ProjectPath::validate))]
     path: PathBuf,
+    /// Serve the build output and a gallery on localhost, reloading after each successful build
+    #[structopt(long)]
+    serve: bool,
+    /// Port for the preview server
+    #[structopt(long, default_value = "8000")]
+    port: u16,
+}
+
+/// What a watched file is used for in the project
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Asset {
+    Manifest,
+    Document,
+    Theme(String),
+    Template(PathBuf),
 }
 
 impl Command {
     pub fn run(self) {
         let path = self.path;
 
-        let data = std::fs::read_to_string(&path.join("emoji.toml"))
+        let data = std::fs::read_to_string(path.join("emoji.toml"))
             .expect("error reading emoji.toml, does the file exist?");
         let mut project = toml::from_str::<Project>(&data)
             .expect("error reading emoji.toml, there may be a syntax error");
@@ -28,64 +47,335 @@ impl Command {
         let (tx, rx) = channel();
         let mut watcher: RecommendedWatcher =
             Watcher::new(tx, Duration::from_secs(2)).expect("error initialising watcher");
+        let mut files = HashMap::new();
+        let mut directories = HashSet::new();
 
-        watcher
-            .watch(project.path.join("emoji.toml"), RecursiveMode::NonRecursive)
-            .expect("error watching emojiset manifest");
+        watch(&mut watcher, &project, &mut files, &mut directories);
 
-        watcher
-            .watch(
-                project.path.join(&project.emojiset.document),
-                RecursiveMode::NonRecursive,
-            )
-            .expect("error watching emojiset document");
+        let server = if self.serve {
+            Some(Server::start(project.path.clone(), self.port))
+        } else {
+            None
+        };
+        let command = BuildCommand {
+            path: project.path.clone(),
+            no_cache: false,
+            jobs: 0,
+            emojis: vec![],
+            themes: vec![],
+            outputs: vec![],
+            dry_run: false,
+            prune: false,
+        };
 
-        watcher
-            .watch(
-                project.path.join(&project.emojiset.stylesheet),
-                RecursiveMode::NonRecursive,
-            )
-            .expect("error watching emojiset stylesheet");
-
-        for theme in &project.themes {
-            watcher
-                .watch(
-                    project.path.join(&theme.stylesheet),
-                    RecursiveMode::NonRecursive,
-                )
-                .expect("error watching emojiset theme");
-        }
+        // The document from the last successful build
+        let mut document = build(&command, &project, &[], None, &server, self.port);
 
-        for template in &project.templates {
-            watcher
-                .watch(
-                    project.path.join(&template.input),
-                    RecursiveMode::NonRecursive,
-                )
-                .expect("error watching emojiset template");
-        }
+        loop {
+            let event = match rx.recv() {
+                Ok(event) => event,
+                Err(e) => {
+                    println!("watch error: {:?}", e);
 
-        let command = BuildCommand { path: project.path };
+                    continue;
+                }
+            };
 
-        build(command.clone());
+            // A file can be used more than once, and events can name it twice:
+            let assets: IndexSet<Asset> = match changed_paths(event) {
+                Some(changed) => changed
+                    .iter()
+                    .flat_map(|path| match files.get(path) {
+                        Some(assets) => assets.clone(),
+                        // New files may match a document glob:
+                        None if project.emojiset.is_document(&project.path, path) => {
+                            vec![Asset::Document]
+                        }
+                        None => vec![],
+                    })
+                    .collect(),
+                None => std::iter::once(Asset::Manifest).collect(),
+            };
+            let assets: Vec<_> = assets.into_iter().collect();
 
-        loop {
-            match rx.recv() {
-                Ok(event) => {
-                    if let DebouncedEvent::Write(_) = event {
-                        build(command.clone());
+            if assets.is_empty() {
+                continue;
+            }
+
+            // Themes and templates may have been added or removed:
+            if assets.contains(&Asset::Manifest) {
+                match reload(&project.path) {
+                    Ok(reloaded) => {
+                        project = reloaded;
+                        watch(&mut watcher, &project, &mut files, &mut directories);
+                    }
+                    Err(error) => {
+                        println!("error reading emoji.toml: {}", error);
+
+                        continue;
                     }
                 }
-                Err(e) => println!("watch error: {:?}", e),
+            } else if assets.contains(&Asset::Document) {
+                // Document globs may match files that were added or removed:
+                watch(&mut watcher, &project, &mut files, &mut directories);
+            }
+
+            if let Some(built) = build(
+                &command,
+                &project,
+                &assets,
+                document.as_ref(),
+                &server,
+                self.port,
+            ) {
+                document = Some(built);
             }
         }
     }
 }
 
-fn build(command: BuildCommand) {
+fn reload(path: &Path) -> Result<Project, toml::de::Error> {
+    let data = std::fs::read_to_string(path.join("emoji.toml")).unwrap_or_default();
+    let mut project = toml::from_str::<Project>(&data)?;
+
+    project.path = path.to_path_buf();
+
+    Ok(project)
+}
+
+/// Work out which parts of the project need rebuilding after assets change
+fn scope(assets: &[Asset], document: &Document, previous: &Document) -> Scope {
+    match assets {
+        [Asset::Document] => Scope {
+            emojis: document
+                .changed_since(previous)
+                .map(|ids| ids.into_iter().collect()),
+            ..Default::default()
+        },
+        _ if assets.iter().all(|asset| matches!(asset, Asset::Theme(_))) => Scope {
+            themes: Some(
+                assets
+                    .iter()
+                    .filter_map(|asset| match asset {
+                        Asset::Theme(name) => Some(name.clone()),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            templates: Some(HashSet::new()),
+            ..Default::default()
+        },
+        _ if assets
+            .iter()
+            .all(|asset| matches!(asset, Asset::Template(_))) =>
+        {
+            Scope {
+                themes: Some(HashSet::new()),
+                emojis: Some(HashSet::new()),
+                templates: Some(
+                    assets
+                        .iter()
+                        .filter_map(|asset| match asset {
+                            Asset::Template(input) => Some(input.clone()),
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                outputs: Some(HashSet::new()),
+                galleries: Some(HashSet::new()),
+            }
+        }
+        _ => Scope::default(),
+    }
+}
+
+/// Remove the exported files of emoji that were deleted or renamed since the
+/// previous build. Only files that builds wrote, according to the build
+/// manifest of each output, are removed.
+fn remove_stale(project: &Project, document: &Document, previous: &Document) {
+    let emojis = |document: &Document| document.emojis.values().cloned().collect::<Vec<_>>();
+    let (current, previous) = (emojis(document), emojis(previous));
+
+    for output in &project.outputs {
+        let mut output = output.clone();
+
+        output.directory = project.path.join(output.directory.clone());
+
+        let current = BuildManifest::files(&project.themes, &current, &output);
+        let stale: BTreeSet<_> = BuildManifest::files(&project.themes, &previous, &output)
+            .difference(&current)
+            .cloned()
+            .collect();
+
+        if stale.is_empty() {
+            continue;
+        }
+
+        let mut manifest = BuildManifest::load(&output.directory);
+        let expected = manifest.files.difference(&stale).cloned().collect();
+
+        for path in manifest.prune(&output.directory, &expected) {
+            println!(
+                "Removed {}",
+                path.strip_prefix(&project.path).unwrap_or(&path).display()
+            );
+        }
+
+        manifest.save(&output.directory);
+    }
+}
+
+/// Watch the directories of every project file, so that files replaced by
+/// editors that save to a temporary file and then rename it are still seen
+fn watch(
+    watcher: &mut RecommendedWatcher,
+    project: &Project,
+    files: &mut HashMap<PathBuf, Vec<Asset>>,
+    directories: &mut HashSet<PathBuf>,
+) {
+    let mut assets = vec![(project.path.join("emoji.toml"), Asset::Manifest)];
+
+    for path in project.emojiset.documents(&project.path) {
+        if project.emojiset.layout == Layout::Files {
+            assets.push((path.with_extension("toml"), Asset::Document));
+        }
+
+        assets.push((path, Asset::Document));
+    }
+
+    assets.extend(project.themes.iter().map(|theme| {
+        (
+            project.path.join(&theme.stylesheet),
+            Asset::Theme(theme.name.clone()),
+        )
+    }));
+    assets.extend(project.templates.iter().map(|template| {
+        (
+            project.path.join(&template.input),
+            Asset::Template(template.input.clone()),
+        )
+    }));
+
+    files.clear();
+
+    for (path, asset) in assets {
+        files.entry(normalize(&path)).or_default().push(asset);
+    }
+
+    // Globs can match files that don't exist yet, so watch where they'd be added:
+    let globs = project
+        .emojiset
+        .document
+        .paths()
+        .into_iter()
+        .filter_map(|path| {
+            project
+                .path
+                .join(path)
+                .ancestors()
+                .skip(1)
+                .find(|directory| !directory.to_string_lossy().contains(['*', '?', '[']))
+                .map(normalize)
+        });
+    let watched: HashSet<_> = files
+        .keys()
+        .filter_map(|path| path.parent().map(Path::to_path_buf))
+        .chain(globs)
+        .filter(|directory| directory.is_dir())
+        .collect();
+
+    for directory in directories.difference(&watched) {
+        let _ = watcher.unwatch(directory);
+    }
+
+    for directory in watched.difference(directories) {
+        watcher
+            .watch(directory, RecursiveMode::NonRecursive)
+            .expect("error watching project directory");
+    }
+
+    *directories = watched;
+}
+
+/// Paths touched by an event, or none when everything should be rebuilt
+fn changed_paths(event: DebouncedEvent) -> Option<Vec<PathBuf>> {
+    match event {
+        DebouncedEvent::Create(path)
+        | DebouncedEvent::Write(path)
+        | DebouncedEvent::Remove(path) => Some(vec![normalize(&path)]),
+        DebouncedEvent::Rename(from, to) => Some(vec![normalize(&from), normalize(&to)]),
+        DebouncedEvent::Rescan => None,
+        _ => Some(Vec::new()),
+    }
+}
+
+/// Resolve a file path through its directory, the file itself may not exist
+fn normalize(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => parent
+            .canonicalize()
+            .unwrap_or_else(|_| parent.to_path_buf())
+            .join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Build the changed assets, everything is built when there is no previous
+/// document. Returns the document if the build succeeded.
+fn build(
+    command: &BuildCommand,
+    project: &Project,
+    assets: &[Asset],
+    previous: Option<&Document>,
+    server: &Option<Server>,
+    port: u16,
+) -> Option<Document> {
     clearscreen::clear().unwrap();
 
-    command.run();
+    // Errors are reported once the build has failed, so panics are only kept
+    // quiet while building:
+    let hook = panic::take_hook();
+
+    panic::set_hook(Box::new(|_| {}));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let document = Document::from(project);
+        let scope = match previous {
+            Some(previous) => {
+                remove_stale(project, &document, previous);
+                scope(assets, &document, previous)
+            }
+            None => Scope::default(),
+        };
+
+        command
+            .build(project.clone(), document.clone(), &scope)
+            .map(|_| document)
+    }))
+    .map_err(panic_message)
+    .and_then(|result| result);
+
+    panic::set_hook(hook);
+
+    let document = match result {
+        Ok(document) => {
+            if let Some(server) = server {
+                server.reload();
+
+                println!("\nServing on http://localhost:{}/", port);
+            }
+
+            Some(document)
+        }
+        Err(error) => {
+            println!("\nBuild failed: {}", error);
+            println!("Keeping the previous build until the next change.");
+
+            None
+        }
+    };
 
     println!("\nWaiting for changes...");
+
+    document
 }
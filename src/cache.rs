@@ -0,0 +1,120 @@
+use crate::document::{Document, Emoji};
+use crate::manifest::{Output, Theme};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{copy, create_dir_all, read_dir, remove_file};
+use std::path::PathBuf;
+
+/// Exported emoji stored by a hash of everything used to render them
+#[derive(Clone, Debug)]
+pub struct Cache {
+    pub directory: PathBuf,
+}
+
+impl Cache {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    /// Hash the source of each emoji, what it references and the rest of its
+    /// file. The rest of each file is only hashed once, however many emoji it has.
+    pub fn sources(document: &Document, emojis: &[Emoji]) -> HashMap<String, String> {
+        let files: Vec<_> = (0..document.files.len())
+            .map(|index| Sha256::digest(document.file_shared_source(index)))
+            .collect();
+
+        emojis
+            .iter()
+            .map(|emoji| {
+                let id = emoji.id().unwrap();
+                let mut hasher = Sha256::new();
+
+                hasher.update(document.dependency_source(&id));
+                hasher.update([0]);
+                hasher.update(files[document.sources[&id]]);
+
+                (id, format!("{:x}", hasher.finalize()))
+            })
+            .collect()
+    }
+
+    /// Hash the source hash of an emoji from `sources`, the theme and its
+    /// stylesheet and output settings. Themes are rendered at the same time,
+    /// so each one gets its own keys even when their stylesheets are the same.
+    pub fn key(source: &str, theme: &Theme, css: &str, output: &Output) -> String {
+        let mut hasher = Sha256::new();
+
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update([0]);
+        hasher.update(&theme.name);
+        hasher.update([0]);
+        hasher.update(source);
+        hasher.update([0]);
+        hasher.update(css);
+        hasher.update([0]);
+        hasher.update([output.trim as u8]);
+        hasher.update(output.size.unwrap_or(0).to_le_bytes());
+        hasher.update([output.format as u8]);
+
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Copy cached files to the output, returns false if any are missing
+    pub fn restore(&self, key: &str, emoji: &Emoji, theme: &Theme, output: &Output) -> bool {
+        let extensions = output.extensions(emoji);
+
+        if !extensions
+            .iter()
+            .all(|extension| self.path(key, extension).is_file())
+        {
+            return false;
+        }
+
+        for extension in &extensions {
+            for name in emoji.names() {
+                let path = output.path(theme, emoji, &name, extension);
+
+                create_dir_all(path.parent().unwrap()).unwrap();
+                copy(self.path(key, extension), path).unwrap();
+            }
+        }
+
+        true
+    }
+
+    /// Copy freshly exported files into the cache
+    pub fn store(&self, key: &str, emoji: &Emoji, theme: &Theme, output: &Output) {
+        create_dir_all(&self.directory).unwrap();
+
+        for extension in output.extensions(emoji) {
+            let path = output.path(theme, emoji, &emoji.name().unwrap(), &extension);
+
+            copy(path, self.path(key, &extension)).unwrap();
+        }
+    }
+
+    /// Remove cached files that aren't for any of the given keys
+    pub fn prune(&self, keys: &HashSet<String>) {
+        let entries = match read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // Frames have extensions like "1.svg", so the key is up to the first dot:
+            let key = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split('.').next());
+
+            if !key.is_some_and(|key| keys.contains(key)) {
+                let _ = remove_file(path);
+            }
+        }
+    }
+
+    fn path(&self, key: &str, extension: &str) -> PathBuf {
+        self.directory.join(format!("{}.{}", key, extension))
+    }
+}
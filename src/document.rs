@@ -1,15 +1,52 @@
-use crate::manifest::Project;
+use crate::manifest::{Layout, MetadataSource, NamePolicy, Project};
 use indexmap::IndexMap;
+use lazy_static::lazy_static;
+use regex::Regex;
 use roxmltree::Node;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
-#[derive(Serialize, Debug)]
+#[derive(Clone, Serialize, Debug)]
 #[serde(rename_all = "snake_case")]
 pub struct Document {
+    /// Svg files that make up the emojiset
     #[serde(skip)]
-    pub svg: String,
+    pub files: Vec<DocumentFile>,
     #[serde(with = "indexmap::serde_seq")]
     pub emojis: IndexMap<String, Emoji>,
+    /// Emoji ids keyed by every name and alias in the document
+    #[serde(skip)]
+    pub names: IndexMap<String, String>,
+    /// Byte range of each emoji group in its file, keyed by id
+    #[serde(skip)]
+    pub ranges: IndexMap<String, Range<usize>>,
+    /// Index of the file that defines each emoji, keyed by id
+    #[serde(skip)]
+    pub sources: IndexMap<String, usize>,
+    /// Where each emoji is defined in its group, keyed by id
+    #[serde(skip)]
+    pub definitions: IndexMap<String, MetadataSource>,
+    /// Names that were changed so they can be used in filenames
+    #[serde(skip)]
+    pub renamed: Vec<Renamed>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DocumentFile {
+    /// Path of the file, relative to the project
+    pub path: PathBuf,
+    pub svg: String,
+    /// Byte range of every element with an id, keyed by id
+    pub elements: IndexMap<String, Range<usize>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Renamed {
+    pub id: String,
+    pub from: String,
+    pub to: String,
 }
 
 impl From<Project> for Document {
@@ -20,43 +57,172 @@ impl From<Project> for Document {
 
 impl From<&Project> for Document {
     fn from(project: &Project) -> Self {
-        let svg = std::fs::read_to_string(&project.path.join(&project.emojiset.document))
-            .expect("error reading emojiset document, does it exist?");
-        let document = roxmltree::Document::parse(&svg)
-            .expect("error reading emojiset document, there may be syntax errors");
+        if let Some(path) = project.emojiset.unmatched(&project.path).first() {
+            panic!(
+                "emojiset document {} doesn't match any files",
+                path.display()
+            );
+        }
 
-        let mut emojis = document
-            .descendants()
-            .fold(IndexMap::new(), |mut emojis, node| {
-                if node.has_tag_name("desc") && node.has_children() {
-                    if let Some(desc) = node.text() {
-                        let emoji: Emoji = toml::from_str(desc).expect(
-                            "invalid toml found in description, try using a # comment instead",
-                        );
-                        let id = get_node_id(&node).expect("missing node id");
-
-                        let emoji = emoji.init(&id);
-
-                        // Insert frame into parent emoji
-                        if let Emoji::Frame { .. } = emoji {
-                            let parent_id =
-                                get_parent_id(&node).expect("missing parent id for frame");
-
-                            let parent = emojis
-                                .get_mut(&parent_id)
-                                .expect("missing parent for frame");
-
-                            if let Emoji::Animation { frames, .. } = parent {
-                                frames.push(emoji);
-                            }
-                        } else {
-                            emojis.insert(id, emoji);
+        let files = project
+            .emojiset
+            .documents(&project.path)
+            .into_iter()
+            .map(|path| {
+                let svg = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+                    panic!(
+                        "error reading emojiset document {}, does it exist?",
+                        path.display()
+                    )
+                });
+                let svg = match project.emojiset.layout {
+                    Layout::Document => svg,
+                    Layout::Files => {
+                        let sidecar = std::fs::read_to_string(path.with_extension("toml")).ok();
+
+                        standalone(&path, &svg, sidecar.as_deref())
+                    }
+                };
+                let path = path
+                    .strip_prefix(&project.path)
+                    .unwrap_or(&path)
+                    .to_path_buf();
+
+                (path, svg)
+            })
+            .collect();
+
+        let mut sources = project.emojiset.metadata.clone();
+
+        // Files are rewritten with a description, whichever sources are used:
+        if project.emojiset.layout == Layout::Files && !sources.contains(&MetadataSource::Desc) {
+            sources.push(MetadataSource::Desc);
+        }
+
+        Self::new(files, project.emojiset.names, &sources)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+}
+
+impl Document {
+    /// Read the emoji from svg files, using the first of the metadata sources
+    /// that defines each group. When there's more than one file, emoji ids
+    /// are qualified with the file they're from, as in `faces.svg#g12`.
+    pub fn new(
+        files: Vec<(PathBuf, String)>,
+        policy: NamePolicy,
+        accepted: &[MetadataSource],
+    ) -> Result<Self, DocumentError> {
+        let qualify = files.len() > 1;
+        let mut emojis = IndexMap::new();
+        let mut ranges = IndexMap::new();
+        let mut sources = IndexMap::new();
+        let mut definitions = IndexMap::new();
+        let mut document_files = Vec::new();
+
+        for (index, (path, svg)) in files.into_iter().enumerate() {
+            let prefix = if qualify {
+                format!("{}#", path.to_string_lossy().replace('\\', "/"))
+            } else {
+                String::new()
+            };
+            let document =
+                roxmltree::Document::parse(&svg).map_err(|error| DocumentError::Syntax {
+                    path: path.clone(),
+                    error: error.to_string(),
+                })?;
+
+            let descs = if accepted.contains(&MetadataSource::Desc) {
+                group_text(&document, "desc")
+            } else {
+                HashMap::new()
+            };
+            let metadata = if accepted.contains(&MetadataSource::Metadata) {
+                group_text(&document, "metadata")
+            } else {
+                HashMap::new()
+            };
+
+            for node in document.descendants() {
+                let element = match node.attribute("id") {
+                    Some(element) if node.has_tag_name("g") => element,
+                    _ => continue,
+                };
+                let definition = accepted.iter().find_map(|source| {
+                    let emoji = match source {
+                        MetadataSource::Desc => descs.get(&node.range().start).map(|desc| {
+                            toml::from_str(desc.text().unwrap()).map_err(|e| e.to_string())
+                        }),
+                        MetadataSource::Metadata => {
+                            metadata.get(&node.range().start).map(|metadata| {
+                                let metadata = metadata.text().unwrap().trim();
+
+                                if metadata.starts_with('{') {
+                                    serde_json::from_str(metadata).map_err(|e| e.to_string())
+                                } else {
+                                    toml::from_str(metadata).map_err(|e| e.to_string())
+                                }
+                            })
                         }
+                        MetadataSource::Attributes => attributes(&node),
+                        MetadataSource::Labels => label(&node),
+                    };
+
+                    emoji.map(|emoji| {
+                        emoji.map(|emoji| (*source, emoji)).map_err(|error| {
+                            DocumentError::Definition {
+                                id: element.to_string(),
+                                path: path.clone(),
+                                source: *source,
+                                error,
+                            }
+                        })
+                    })
+                });
+                let (source, emoji) = match definition.transpose()? {
+                    Some(definition) => definition,
+                    None => continue,
+                };
+                let id = format!("{}{}", prefix, element);
+                let emoji = emoji.init(&id);
+
+                // Insert frame into parent emoji
+                if let Emoji::Frame { .. } = emoji {
+                    let parent_id = node
+                        .ancestors()
+                        .skip(1)
+                        .filter(|node| node.has_tag_name("g"))
+                        .filter_map(|node| node.attribute("id"))
+                        .map(|parent_id| format!("{}{}", prefix, parent_id))
+                        .find(|parent_id| emojis.get(parent_id).is_some_and(Emoji::is_animation))
+                        .ok_or_else(|| DocumentError::MissingParent {
+                            id: element.to_string(),
+                            path: path.clone(),
+                        })?;
+
+                    if let Some(Emoji::Animation { frames, .. }) = emojis.get_mut(&parent_id) {
+                        frames.push(emoji);
                     }
+                } else {
+                    ranges.insert(id.clone(), node.range());
+                    sources.insert(id.clone(), index);
+                    definitions.insert(id.clone(), source);
+                    emojis.insert(id, emoji);
                 }
+            }
+
+            let elements = document
+                .descendants()
+                .filter_map(|node| Some((node.attribute("id")?.to_string(), node.range())))
+                .collect();
 
-                emojis
+            drop(document);
+            document_files.push(DocumentFile {
+                path,
+                svg,
+                elements,
             });
+        }
 
         // Make sure animation frames are sorted by position
         emojis.iter_mut().for_each(|(_, emoji)| {
@@ -70,36 +236,683 @@ impl From<&Project> for Document {
             }
         });
 
-        Self { svg, emojis }
+        let renamed = sanitize_names(&mut emojis, policy)?;
+        let names = index_names(&emojis)?;
+
+        Ok(Self {
+            files: document_files,
+            emojis,
+            names,
+            ranges,
+            sources,
+            definitions,
+            renamed,
+        })
+    }
+
+    /// The file that defines an emoji
+    pub fn file(&self, id: &str) -> &DocumentFile {
+        &self.files[self.sources[id]]
+    }
+
+    /// Split emoji up by the file that defines them, in the order of the files
+    pub fn group(&self, emojis: &[Emoji]) -> Vec<(&DocumentFile, Vec<Emoji>)> {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| {
+                let emojis = emojis
+                    .iter()
+                    .filter(|emoji| self.sources.get(&emoji.id().unwrap()) == Some(&index))
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                (file, emojis)
+            })
+            .filter(|(_, emojis)| !emojis.is_empty())
+            .collect()
+    }
+
+    /// Source of the group that defines an emoji
+    pub fn source(&self, id: &str) -> Option<&str> {
+        let range = self.ranges.get(id)?;
+
+        Some(&self.file(id).svg[range.clone()])
+    }
+
+    /// Source of an emoji group followed by the source of every element it
+    /// references, such as gradients and clip paths in the defs
+    pub fn dependency_source(&self, id: &str) -> String {
+        lazy_static! {
+            static ref REFERENCE_RE: Regex =
+                Regex::new(r#"(?:url\(\s*['"]?#|href\s*=\s*['"]#)([^'")\s]+)"#).unwrap();
+        }
+
+        let file = self.file(id);
+        let mut source = String::new();
+        let mut seen = HashSet::new();
+        let mut queue = vec![element_id(id).to_string()];
+
+        while let Some(id) = queue.pop() {
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+
+            if let Some(range) = file.elements.get(&id) {
+                let element = &file.svg[range.clone()];
+
+                source.push_str(element);
+                queue.extend(
+                    REFERENCE_RE
+                        .captures_iter(element)
+                        .map(|captures| captures[1].to_string()),
+                );
+            }
+        }
+
+        source
+    }
+
+    /// Source of everything in the document that isn't an emoji group
+    pub fn shared_source(&self) -> String {
+        let mut shared = String::new();
+
+        for (index, file) in self.files.iter().enumerate() {
+            shared.push_str(&file.path.to_string_lossy());
+            shared.push_str(&self.file_shared_source(index));
+        }
+
+        shared
+    }
+
+    /// Source of everything in one file that isn't an emoji group, such as the
+    /// layers emoji are in and any shared styles
+    pub fn file_shared_source(&self, index: usize) -> String {
+        let file = &self.files[index];
+        let mut shared = String::new();
+        let mut start = 0;
+
+        for (id, range) in &self.ranges {
+            // Emoji nested in other emoji are already covered by their parent:
+            if self.sources[id] != index || range.start < start {
+                continue;
+            }
+
+            shared.push_str(&file.svg[start..range.start]);
+            start = range.end;
+        }
+
+        shared.push_str(&file.svg[start..]);
+
+        shared
+    }
+
+    /// Ids of emoji that were added or changed since an earlier version of the
+    /// document, or `None` when something shared by every emoji changed
+    pub fn changed_since(&self, previous: &Document) -> Option<Vec<String>> {
+        if self.shared_source() != previous.shared_source() {
+            return None;
+        }
+
+        Some(
+            self.emojis
+                .keys()
+                .filter(|id| self.source(id) != previous.source(id))
+                .cloned()
+                .collect(),
+        )
     }
 }
 
-fn get_node_id(node: &Node) -> Option<String> {
-    node.ancestors().find_map(|node| {
-        if node.has_tag_name("g") {
-            if let Some(id) = node.attribute("id") {
-                return Some(id.into());
+/// Id given to the group that wraps a standalone emoji file
+const STANDALONE_ID: &str = "emoji-crafter";
+
+/// Rewrite a file that is an emoji of its own as a group with a toml description,
+/// so it can be read like any other document. The name is taken from the filename
+/// unless the sidecar toml file, or a `<desc>` on the root element, gives one.
+/// Animation frames are the top-level groups of the file, in order, and last for
+/// `delay` ms unless a group has a `<desc>` with a delay of its own.
+pub fn standalone(path: &Path, svg: &str, sidecar: Option<&str>) -> String {
+    let document = roxmltree::Document::parse(svg).unwrap_or_else(|e| {
+        panic!(
+            "error reading emoji {}, there may be syntax errors: {}",
+            path.display(),
+            e
+        )
+    });
+    let root = document.root_element();
+    let metadata = sidecar.or_else(|| desc(&root)).unwrap_or_default();
+    let mut table: toml::value::Table = toml::from_str(metadata).unwrap_or_else(|e| {
+        panic!(
+            "invalid toml found in the description of {}: {}",
+            path.display(),
+            e
+        )
+    });
+    let name = path.file_stem().unwrap().to_string_lossy().to_string();
+
+    table.entry("name").or_insert_with(|| name.into());
+    table.entry("type").or_insert_with(|| "image".into());
+
+    let delay = table
+        .remove("delay")
+        .and_then(|delay| delay.as_integer())
+        .unwrap_or(100);
+    let is_animation = matches!(
+        table.get("type").and_then(|kind| kind.as_str()),
+        Some("animation" | "animated")
+    );
+    let mut edits = Vec::new();
+
+    // Descriptions have been read already and would otherwise be read as emoji:
+    for node in document
+        .descendants()
+        .filter(|node| node.has_tag_name("desc"))
+    {
+        edits.push((node.range(), String::new()));
+    }
+
+    let start = start_tag_end(svg, &root);
+    let end = svg[..root.range().end]
+        .rfind("</")
+        .filter(|end| *end >= start)
+        .unwrap_or(start);
+    // Themes replace the first stylesheet, so make sure there is one:
+    let style = if document
+        .descendants()
+        .any(|node| node.has_tag_name("style"))
+    {
+        ""
+    } else {
+        "<style></style>"
+    };
+
+    let group = format!(
+        r#"{}<g id="{}"><desc>{}</desc>"#,
+        style,
+        STANDALONE_ID,
+        escape_xml(&toml::to_string(&table).unwrap())
+    );
+
+    // An empty file can close its root in the start tag, so give it an end tag:
+    if svg[..start].ends_with("/>") {
+        let tag = svg[root.range().start + 1..]
+            .split(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .next()
+            .unwrap();
+
+        edits.push((start - 2..start, format!(">{}</g></{}>", group, tag)));
+    } else {
+        edits.push((start..start, group));
+        edits.push((end..end, "</g>".into()));
+    }
+
+    if is_animation {
+        let frames = root
+            .children()
+            .filter(|node| node.has_tag_name("g") && node.has_children());
+
+        for (index, frame) in frames.enumerate() {
+            let delay = desc(&frame)
+                .and_then(|desc| toml::from_str::<toml::value::Table>(desc).ok())
+                .and_then(|table| table.get("delay")?.as_integer())
+                .unwrap_or(delay);
+            let start = start_tag_end(svg, &frame);
+
+            if frame.attribute("id").is_none() {
+                let at = frame.range().start + 2;
+
+                edits.push((
+                    at..at,
+                    format!(r#" id="{}-frame-{}""#, STANDALONE_ID, index + 1),
+                ));
             }
+
+            edits.push((
+                start..start,
+                format!(
+                    "<desc>type = \"frame\"\ndelay = {}\nposition = {}</desc>",
+                    delay,
+                    index + 1
+                ),
+            ));
         }
+    }
 
-        None
-    })
+    // Apply from the end, so earlier edits don't move later ones:
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse((range.start, range.end)));
+
+    let mut svg = svg.to_string();
+
+    for (range, text) in edits {
+        svg.replace_range(range, &text);
+    }
+
+    svg
 }
 
-fn get_parent_id(node: &Node) -> Option<String> {
-    node.ancestors()
-        .collect::<Vec<_>>()
+/// Text of the `<desc>` that is a direct child of a node
+fn desc<'a>(node: &Node<'a, '_>) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.has_tag_name("desc"))
+        .and_then(|desc| desc.text())
+}
+
+/// Position just after the start tag of an element
+fn start_tag_end(svg: &str, node: &Node) -> usize {
+    let from = node
+        .attributes()
         .iter()
-        .rev()
-        .find_map(|node| {
-            if node.has_tag_name("g") {
-                if let Some(id) = node.attribute("id") {
-                    return Some(id.into());
+        .map(|attribute| attribute.value_range().end)
+        .max()
+        .unwrap_or(node.range().start);
+
+    from + svg[from..].find('>').unwrap() + 1
+}
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Id of the svg element for an emoji or frame id, without the file it's from
+pub fn element_id(id: &str) -> &str {
+    id.rsplit('#').next().unwrap()
+}
+
+/// Map every emoji name and alias to the id of the group that defines it
+pub fn index_names(
+    emojis: &IndexMap<String, Emoji>,
+) -> Result<IndexMap<String, String>, DocumentError> {
+    let mut names: IndexMap<String, String> = IndexMap::new();
+
+    for (id, emoji) in emojis {
+        for name in emoji.names() {
+            if let Some(first) = names.get(&name) {
+                return Err(DocumentError::DuplicateName {
+                    name,
+                    first: first.clone(),
+                    second: id.clone(),
+                });
+            }
+
+            names.insert(name, id.clone());
+        }
+    }
+
+    Ok(names)
+}
+
+/// Make sure every name and alias can be used as a filename, following the
+/// policy, and return the names that were changed
+pub fn sanitize_names(
+    emojis: &mut IndexMap<String, Emoji>,
+    policy: NamePolicy,
+) -> Result<Vec<Renamed>, DocumentError> {
+    let mut invalid = Vec::new();
+    let mut renamed = Vec::new();
+
+    for (id, emoji) in emojis.iter_mut() {
+        let names = match emoji {
+            Emoji::Animation { name, aliases, .. } | Emoji::Image { name, aliases, .. } => {
+                std::iter::once(name).chain(aliases.iter_mut())
+            }
+            _ => continue,
+        };
+
+        for name in names {
+            match policy {
+                NamePolicy::Reject => {
+                    if let Some(reason) = invalid_name(name) {
+                        invalid.push((id.clone(), name.clone(), reason));
+                    }
+                }
+                NamePolicy::Replace | NamePolicy::Transliterate => {
+                    let sanitized = if policy == NamePolicy::Transliterate {
+                        replace_invalid(&deunicode::deunicode(name))
+                    } else {
+                        replace_invalid(name)
+                    };
+
+                    if &sanitized != name {
+                        renamed.push(Renamed {
+                            id: id.clone(),
+                            from: std::mem::replace(name, sanitized.clone()),
+                            to: sanitized,
+                        });
+                    }
                 }
             }
+        }
+    }
+
+    if invalid.is_empty() {
+        Ok(renamed)
+    } else {
+        Err(DocumentError::InvalidNames(invalid))
+    }
+}
+
+const RESERVED_CHARACTERS: [char; 9] = ['/', '\\', '<', '>', ':', '"', '|', '?', '*'];
+
+/// Why a name can't be used as a filename, if it can't
+pub fn invalid_name(name: &str) -> Option<&'static str> {
+    if name.is_empty() {
+        Some("is empty")
+    } else if name == "." || name == ".." {
+        Some("is a relative path")
+    } else if name
+        .chars()
+        .any(|c| c.is_control() || RESERVED_CHARACTERS.contains(&c))
+    {
+        Some("contains characters that can't be used in filenames")
+    } else if name.ends_with(['.', ' ']) {
+        Some("ends with a dot or space")
+    } else if is_reserved(name) {
+        Some("is reserved on Windows")
+    } else {
+        None
+    }
+}
+
+/// Windows device names can't be used as filenames, even with an extension
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).to_uppercase();
 
-            None
+    matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem[3..].chars().all(|c| c.is_ascii_digit() && c != '0'))
+}
+
+fn replace_invalid(name: &str) -> String {
+    let mut name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || RESERVED_CHARACTERS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
         })
+        .collect();
+
+    // Trailing dots and spaces are dropped by Windows, which also covers "." and "..":
+    let kept = name.trim_end_matches(['.', ' ']).len();
+    let trailing = name.len() - kept;
+
+    name.truncate(kept);
+    name.push_str(&"_".repeat(trailing));
+
+    if name.is_empty() || is_reserved(&name) {
+        name.push('_');
+    }
+
+    name
+}
+
+#[derive(Debug)]
+pub enum DocumentError {
+    DuplicateName {
+        name: String,
+        first: String,
+        second: String,
+    },
+    /// Names that can't be used as filenames, with the emoji id and the reason
+    InvalidNames(Vec<(String, String, &'static str)>),
+    /// A file that isn't valid xml
+    Syntax { path: PathBuf, error: String },
+    /// A group whose definition in one of the metadata sources can't be read
+    Definition {
+        id: String,
+        path: PathBuf,
+        source: MetadataSource,
+        error: String,
+    },
+    /// A frame that isn't inside an animation
+    MissingParent { id: String, path: PathBuf },
+}
+
+impl std::fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use DocumentError::*;
+
+        match self {
+            DuplicateName {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "emoji name \"{}\" is used by both \"{}\" and \"{}\", names and aliases must be unique",
+                name, first, second
+            ),
+            InvalidNames(names) => {
+                writeln!(f, "some emoji names can't be used as filenames:")?;
+
+                for (id, name, reason) in names {
+                    writeln!(f, "  \"{}\" in \"{}\" {}", name, id, reason)?;
+                }
+
+                write!(
+                    f,
+                    "rename them, or set names = \"replace\" or \"transliterate\" under [emojiset] to fix them when building"
+                )
+            }
+            Syntax { path, error } => write!(
+                f,
+                "error reading emojiset document {}, there may be syntax errors: {}",
+                path.display(),
+                error
+            ),
+            Definition {
+                id,
+                path,
+                source: MetadataSource::Desc,
+                error,
+            } => write!(
+                f,
+                "invalid toml found in the description of \"{}\" in {}, try using a # comment instead: {}",
+                id,
+                path.display(),
+                error
+            ),
+            Definition {
+                id,
+                path,
+                source: MetadataSource::Metadata,
+                error,
+            } => write!(
+                f,
+                "invalid metadata found in \"{}\" in {}, it should be json or toml: {}",
+                id,
+                path.display(),
+                error
+            ),
+            Definition {
+                id,
+                path,
+                source: MetadataSource::Attributes,
+                error,
+            } => write!(
+                f,
+                "invalid data-emoji attributes on \"{}\" in {}: {}",
+                id,
+                path.display(),
+                error
+            ),
+            Definition {
+                id,
+                path,
+                source: MetadataSource::Labels,
+                error,
+            } => write!(f, "invalid label on \"{}\" in {}: {}", id, path.display(), error),
+            MissingParent { id, path } => write!(
+                f,
+                "missing parent animation for frame \"{}\" in {}",
+                id,
+                path.display()
+            ),
+        }
+    }
+}
+
+/// The first element with a tag and some text in each group with an id, keyed
+/// by where the group starts. Elements outside of any group with an id, like
+/// the metadata editors add to the document, are skipped.
+pub(crate) fn group_text<'a>(
+    document: &'a roxmltree::Document,
+    tag: &str,
+) -> HashMap<usize, Node<'a, 'a>> {
+    let mut texts = HashMap::new();
+
+    for node in document.descendants() {
+        if !node.has_tag_name(tag) || !node.has_children() {
+            continue;
+        }
+
+        if node.text().is_none_or(|text| text.trim().is_empty()) {
+            continue;
+        }
+
+        let group = match node
+            .ancestors()
+            .find(|node| node.has_tag_name("g") && node.has_attribute("id"))
+        {
+            Some(group) => group,
+            None => continue,
+        };
+
+        texts.entry(group.range().start).or_insert(node);
+    }
+
+    texts
+}
+
+/// The emoji defined by `data-emoji-*` attributes on a group, if it has any.
+/// The type is optional, groups with a delay or position are frames, and
+/// groups with frames in them are animations.
+fn attributes(node: &Node) -> Option<Result<Emoji, String>> {
+    const PREFIX: &str = "data-emoji-";
+
+    if !node
+        .attributes()
+        .iter()
+        .any(|a| a.name().starts_with(PREFIX))
+    {
+        return None;
+    }
+
+    let mut table = toml::value::Table::new();
+
+    for attribute in node.attributes() {
+        let key = match attribute.name().strip_prefix(PREFIX) {
+            Some(key) => key,
+            None => continue,
+        };
+        let value = attribute.value();
+        let value = match key {
+            "aliases" | "tags" => toml::Value::Array(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .map(|value| toml::Value::String(value.into()))
+                    .collect(),
+            ),
+            "delay" | "position" => match value.trim().trim_end_matches("ms").parse::<i64>() {
+                Ok(number) => toml::Value::Integer(number),
+                Err(_) => return Some(Err(format!("{}{} must be a number", PREFIX, key))),
+            },
+            _ => toml::Value::String(value.into()),
+        };
+
+        table.insert(key.into(), value);
+    }
+
+    if !table.contains_key("type") {
+        let kind = if table.contains_key("delay") || table.contains_key("position") {
+            "frame"
+        } else if node
+            .descendants()
+            .any(|node| node.has_attribute(&*format!("{}delay", PREFIX)))
+        {
+            "animation"
+        } else {
+            "image"
+        };
+
+        table.insert("type".into(), toml::Value::String(kind.into()));
+    }
+
+    Some(
+        toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| e.to_string()),
+    )
+}
+
+/// The emoji named by an Inkscape label like `emoji:bunne`, or the frame of
+/// one labelled like `frame:2@60ms`. Groups with frames in them are animations.
+fn label(node: &Node) -> Option<Result<Emoji, String>> {
+    let label = inkscape_label(node)?;
+
+    if let Some(name) = label.strip_prefix("emoji:") {
+        let name = name.trim();
+
+        if name.is_empty() {
+            return Some(Err("emoji labels need a name, like emoji:bunne".into()));
+        }
+
+        let animated = node
+            .descendants()
+            .skip(1)
+            .any(|node| inkscape_label(&node).is_some_and(|label| label.starts_with("frame:")));
+        let (id, aliases, tags, category) = (String::new(), vec![], vec![], None);
+
+        return Some(Ok(if animated {
+            Emoji::Animation {
+                id,
+                name: name.into(),
+                aliases,
+                tags,
+                category,
+                frames: vec![],
+            }
+        } else {
+            Emoji::Image {
+                id,
+                name: name.into(),
+                aliases,
+                tags,
+                category,
+            }
+        }));
+    }
+
+    let frame = label.strip_prefix("frame:")?;
+    let (position, delay) = match frame.split_once('@') {
+        Some((position, delay)) => (position.trim(), delay.trim().trim_end_matches("ms")),
+        None => return Some(Err("frame labels need a delay, like frame:2@60ms".into())),
+    };
+
+    Some(match (position.parse(), delay.parse()) {
+        (Ok(position), Ok(delay)) => Ok(Emoji::Frame {
+            id: String::new(),
+            delay,
+            position,
+        }),
+        _ => Err(format!(
+            "\"{}\" should be a frame position and delay in ms, like frame:2@60ms",
+            label
+        )),
+    })
+}
+
+fn inkscape_label<'a>(node: &Node<'a, '_>) -> Option<&'a str> {
+    node.attribute(("http://www.inkscape.org/namespaces/inkscape", "label"))
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -111,6 +924,12 @@ pub enum Emoji {
         #[serde(skip)]
         id: String,
         name: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        aliases: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        category: Option<String>,
         #[serde(skip_deserializing)]
         frames: Vec<Emoji>,
     },
@@ -119,6 +938,12 @@ pub enum Emoji {
         #[serde(skip)]
         id: String,
         name: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        aliases: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        category: Option<String>,
     },
     Frame {
         #[serde(skip_deserializing)]
@@ -130,6 +955,14 @@ pub enum Emoji {
 }
 
 impl Emoji {
+    pub fn is_image(&self) -> bool {
+        if let Emoji::Image { .. } = self {
+            return true;
+        }
+
+        false
+    }
+
     pub fn is_animation(&self) -> bool {
         if let Emoji::Animation { .. } = self {
             return true;
@@ -138,25 +971,14 @@ impl Emoji {
         false
     }
 
-    pub fn init(self, id: &str) -> Self {
-        match &self {
-            Emoji::Animation { name, frames, .. } => Emoji::Animation {
-                id: id.into(),
-                name: name.clone(),
-                frames: frames.clone(),
-            },
-            Emoji::Frame {
-                delay, position, ..
-            } => Emoji::Frame {
-                id: id.into(),
-                delay: *delay,
-                position: *position,
-            },
-            Emoji::Image { name, .. } => Emoji::Image {
-                id: id.into(),
-                name: name.clone(),
-            },
+    pub fn init(mut self, id: &str) -> Self {
+        match &mut self {
+            Emoji::Animation { id: emoji_id, .. }
+            | Emoji::Frame { id: emoji_id, .. }
+            | Emoji::Image { id: emoji_id, .. } => *emoji_id = id.into(),
         }
+
+        self
     }
 
     pub fn id(&self) -> Option<String> {
@@ -175,6 +997,41 @@ impl Emoji {
         }
     }
 
+    pub fn aliases(&self) -> Vec<String> {
+        match self {
+            Emoji::Animation { aliases, .. } | Emoji::Image { aliases, .. } => aliases.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The name followed by any aliases
+    pub fn names(&self) -> Vec<String> {
+        self.name().into_iter().chain(self.aliases()).collect()
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        match self {
+            Emoji::Animation { tags, .. } | Emoji::Image { tags, .. } => tags.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn category(&self) -> Option<String> {
+        match self {
+            Emoji::Animation { category, .. } | Emoji::Image { category, .. } => category.clone(),
+            _ => None,
+        }
+    }
+
+    /// File extensions the emoji is exported with
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Emoji::Animation { .. } => &["webp", "gif"],
+            Emoji::Image { .. } => &["png"],
+            _ => &[],
+        }
+    }
+
     pub fn frames(&self) -> Vec<Emoji> {
         match self {
             Emoji::Animation { frames, .. } => frames.clone(),
@@ -182,6 +1039,18 @@ impl Emoji {
         }
     }
 
+    pub fn delay(&self) -> Option<usize> {
+        match self {
+            Emoji::Frame { delay, .. } => Some(*delay),
+            _ => None,
+        }
+    }
+
+    /// Total length of an animation in ms
+    pub fn duration(&self) -> usize {
+        self.frames().iter().filter_map(|frame| frame.delay()).sum()
+    }
+
     pub fn position(&self) -> Option<usize> {
         match self {
             Emoji::Frame { position, .. } => Some(*position),
@@ -0,0 +1,761 @@
+use crate::document::{element_id, escape_xml, group_text, invalid_name, Document, Emoji};
+use crate::manifest::{Layout, MetadataSource, Project};
+use crate::renderer::emoji_renderer::parse_tree;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use usvg::NodeExt;
+
+const INKSCAPE: &str = "http://www.inkscape.org/namespaces/inkscape";
+const XLINK: &str = "http://www.w3.org/1999/xlink";
+
+/// Size of grid cells in documents without any emoji to measure
+const DEFAULT_CELL: f64 = 128.0;
+
+/// Adds emoji groups to a file of the document, on free cells of the grid
+/// its emoji are laid out on. The rest of the file is kept exactly as it was.
+pub struct Scaffold {
+    svg: String,
+    source: MetadataSource,
+    /// Prefix the inkscape namespace is bound to, if it's declared
+    inkscape: Option<String>,
+    /// Prefix the xlink namespace is bound to, if it's declared
+    xlink: Option<String>,
+    /// Element ids already used in the file
+    ids: HashSet<String>,
+    /// Every name and alias already used in the document
+    names: HashSet<String>,
+    cell: f64,
+    columns: usize,
+    /// Grid cells that have something on them, as (column, row)
+    used: HashSet<(usize, usize)>,
+    groups: Vec<String>,
+}
+
+/// Where a group was added, in document units
+#[derive(Clone, Copy, Debug)]
+pub struct Placement {
+    pub x: f64,
+    pub y: f64,
+    pub size: f64,
+}
+
+/// How a new group defines its emoji or frame
+enum Definition<'a> {
+    Emoji { name: &'a str, animated: bool },
+    Frame { delay: usize, position: usize },
+}
+
+impl Scaffold {
+    /// Prepare to add emoji to a file of the document, defined with the first
+    /// of the accepted metadata sources that can be written to the file
+    pub fn new(
+        document: &Document,
+        file: usize,
+        accepted: &[MetadataSource],
+    ) -> Result<Self, EditError> {
+        let svg = document.files[file].svg.clone();
+        let (inkscape, xlink) = match roxmltree::Document::parse(&svg) {
+            Ok(xml) => {
+                let prefix = |uri| xml.root_element().lookup_prefix(uri).map(String::from);
+
+                (prefix(INKSCAPE), prefix(XLINK))
+            }
+            Err(_) => (None, None),
+        };
+        let source = *accepted
+            .iter()
+            .find(|source| **source != MetadataSource::Labels || inkscape.is_some())
+            .ok_or(EditError::NoSource)?;
+
+        let rtree = parse_tree(svg.as_bytes());
+        let boxes: Vec<_> = document
+            .emojis
+            .iter()
+            .filter(|(id, _)| document.sources[id.as_str()] == file)
+            .flat_map(|(id, emoji)| match emoji {
+                Emoji::Animation { .. } => emoji.frames().iter().map(|f| f.id().unwrap()).collect(),
+                _ => vec![id.clone()],
+            })
+            .filter_map(|id| rtree.node_by_id(element_id(&id))?.calculate_bbox())
+            .collect();
+
+        // The most common emoji size is taken to be the size of the grid:
+        let mut sizes = HashMap::new();
+
+        for bbox in &boxes {
+            *sizes
+                .entry(bbox.width().max(bbox.height()).round() as usize)
+                .or_insert(0) += 1;
+        }
+
+        let cell = sizes
+            .into_iter()
+            .max_by_key(|(size, count)| (*count, *size))
+            .map(|(size, _)| size as f64)
+            .filter(|size| *size > 0.0)
+            .unwrap_or(DEFAULT_CELL);
+        let width = rtree.svg_node().view_box.rect.width();
+        let columns = ((width / cell).floor() as usize).max(1);
+        let mut used = HashSet::new();
+
+        for bbox in boxes {
+            let cells = |start: f64, length: f64| {
+                let first = (start / cell).floor().max(0.0) as usize;
+                let last = ((start + length) / cell - 0.001).ceil() as usize;
+
+                first..last.max(first + 1)
+            };
+
+            for column in cells(bbox.x(), bbox.width()) {
+                for row in cells(bbox.y(), bbox.height()) {
+                    used.insert((column, row));
+                }
+            }
+        }
+
+        Ok(Self {
+            svg,
+            source,
+            inkscape,
+            xlink,
+            ids: document.files[file].elements.keys().cloned().collect(),
+            names: document.names.keys().cloned().collect(),
+            cell,
+            columns,
+            used,
+            groups: Vec::new(),
+        })
+    }
+
+    /// Add an image emoji, its content is given the cell it's placed on
+    pub fn image(
+        &mut self,
+        name: &str,
+        content: impl FnOnce(Placement) -> String,
+    ) -> Result<Placement, EditError> {
+        self.check_name(name)?;
+
+        let placement = self.place(1)[0];
+        let id = self.id();
+        let group = self.group(
+            &id,
+            Definition::Emoji {
+                name,
+                animated: false,
+            },
+            &content(placement),
+            "  ",
+        );
+
+        self.groups.push(group);
+
+        Ok(placement)
+    }
+
+    /// Add an animation with a frame for each delay, placed side by side.
+    /// The content of each frame is given its index and the cell it's on.
+    pub fn animation(
+        &mut self,
+        name: &str,
+        delays: &[usize],
+        mut content: impl FnMut(usize, Placement) -> String,
+    ) -> Result<Placement, EditError> {
+        self.check_name(name)?;
+
+        let placements = self.place(delays.len().max(1));
+        let id = self.id();
+        let frames: String = delays
+            .iter()
+            .zip(&placements)
+            .enumerate()
+            .map(|(index, (delay, placement))| {
+                let id = self.id();
+
+                self.group(
+                    &id,
+                    Definition::Frame {
+                        delay: *delay,
+                        position: index + 1,
+                    },
+                    &content(index, *placement),
+                    "    ",
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n    ");
+        let group = self.group(
+            &id,
+            Definition::Emoji {
+                name,
+                animated: true,
+            },
+            &frames,
+            "  ",
+        );
+
+        self.groups.push(group);
+
+        Ok(placements[0])
+    }
+
+    /// Name of the attribute to link to images with, using xlink when the file declares it
+    pub fn href(&self) -> String {
+        match &self.xlink {
+            Some(prefix) => format!("{}:href", prefix),
+            None => "href".into(),
+        }
+    }
+
+    /// The file with every group added, before the closing tag of the root
+    pub fn finish(self) -> String {
+        let end = roxmltree::Document::parse(&self.svg)
+            .map(|xml| xml.root_element().range().end)
+            .unwrap_or(self.svg.len());
+        let position = self.svg[..end].rfind("</").unwrap_or(end);
+        let mut svg = self.svg;
+        let mut groups = String::new();
+
+        if !svg[..position].ends_with('\n') {
+            groups.push('\n');
+        }
+
+        for group in &self.groups {
+            groups.push_str("  ");
+            groups.push_str(group);
+            groups.push('\n');
+        }
+
+        svg.insert_str(position, &groups);
+        svg
+    }
+
+    fn check_name(&mut self, name: &str) -> Result<(), EditError> {
+        if let Some(reason) = invalid_name(name) {
+            return Err(EditError::InvalidName(name.into(), reason));
+        }
+
+        if !self.names.insert(name.into()) {
+            return Err(EditError::NameTaken(name.into()));
+        }
+
+        Ok(())
+    }
+
+    /// An element id that isn't used in the file yet
+    fn id(&mut self) -> String {
+        let id = (1..)
+            .map(|n| format!("g{}", n))
+            .find(|id| !self.ids.contains(id))
+            .unwrap();
+
+        self.ids.insert(id.clone());
+
+        id
+    }
+
+    /// Find a row with enough free cells next to each other and use them.
+    /// Runs too long for the page start a row of their own.
+    fn place(&mut self, count: usize) -> Vec<Placement> {
+        let columns = self.columns.max(count);
+
+        for row in 0.. {
+            for column in 0..=(columns - count) {
+                if count > self.columns && column > 0 {
+                    break;
+                }
+
+                let cells: Vec<_> = (column..column + count).map(|c| (c, row)).collect();
+
+                if cells.iter().any(|cell| self.used.contains(cell)) {
+                    continue;
+                }
+
+                self.used.extend(cells.iter().copied());
+
+                return cells
+                    .into_iter()
+                    .map(|(column, row)| Placement {
+                        x: column as f64 * self.cell,
+                        y: row as f64 * self.cell,
+                        size: self.cell,
+                    })
+                    .collect();
+            }
+        }
+
+        unreachable!("there is always a free row")
+    }
+
+    /// Markup of a group, indented to sit at the given depth
+    fn group(&self, id: &str, definition: Definition, content: &str, indent: &str) -> String {
+        let fields = match definition {
+            Definition::Emoji { name, animated } => vec![
+                ("name", toml::Value::String(name.into())),
+                (
+                    "type",
+                    toml::Value::String(if animated { "animation" } else { "image" }.into()),
+                ),
+            ],
+            Definition::Frame { delay, position } => vec![
+                ("type", toml::Value::String("frame".into())),
+                ("delay", toml::Value::Integer(delay as i64)),
+                ("position", toml::Value::Integer(position as i64)),
+            ],
+        };
+        let toml = || {
+            fields
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, value))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let (attributes, child) = match self.source {
+            MetadataSource::Desc => (
+                String::new(),
+                format!("<desc>{}</desc>", escape_xml(&toml())),
+            ),
+            MetadataSource::Metadata => (
+                String::new(),
+                format!("<metadata>{}</metadata>", escape_xml(&toml())),
+            ),
+            MetadataSource::Attributes => (
+                fields
+                    .iter()
+                    .map(|(key, value)| {
+                        let value = match value {
+                            toml::Value::String(value) => value.clone(),
+                            value => value.to_string(),
+                        };
+
+                        format!(r#" data-emoji-{}="{}""#, key, escape_xml(&value))
+                    })
+                    .collect(),
+                String::new(),
+            ),
+            MetadataSource::Labels => {
+                let label = match definition {
+                    Definition::Emoji { name, .. } => format!("emoji:{}", name),
+                    Definition::Frame { delay, position } => {
+                        format!("frame:{}@{}ms", position, delay)
+                    }
+                };
+
+                (
+                    format!(
+                        r#" {}:label="{}""#,
+                        self.inkscape.as_deref().unwrap_or("inkscape"),
+                        escape_xml(&label)
+                    ),
+                    String::new(),
+                )
+            }
+        };
+        let children: Vec<_> = [child.as_str(), content]
+            .iter()
+            .filter(|child| !child.is_empty())
+            .copied()
+            .collect();
+
+        format!(
+            "<g id=\"{}\"{}>\n{indent}  {}\n{indent}</g>",
+            id,
+            attributes,
+            children.join(&format!("\n{}  ", indent)),
+            indent = indent
+        )
+    }
+}
+
+/// A change to a file of the emojiset, with paths relative to the project
+#[derive(Clone, Debug)]
+pub enum Change {
+    Write(PathBuf, String),
+    Move(PathBuf, PathBuf),
+}
+
+/// Rename an emoji, or one of its aliases, where it's defined, optionally
+/// keeping the old name as an alias. Returns the changes to make to files.
+pub fn rename(
+    project: &Project,
+    document: &Document,
+    old: &str,
+    new: &str,
+    keep: bool,
+) -> Result<Vec<Change>, EditError> {
+    let id = document
+        .names
+        .get(old)
+        .ok_or_else(|| EditError::UnknownName(old.into()))?;
+
+    if let Some(reason) = invalid_name(new) {
+        return Err(EditError::InvalidName(new.into(), reason));
+    }
+
+    if document.names.contains_key(new) {
+        return Err(EditError::NameTaken(new.into()));
+    }
+
+    let is_alias = document.emojis[id].name().as_deref() != Some(old);
+    // Names may have been changed to be used in filenames:
+    let written = document
+        .renamed
+        .iter()
+        .find(|renamed| &renamed.id == id && renamed.to == old)
+        .map_or(old, |renamed| renamed.from.as_str());
+    let file = document.file(id);
+
+    if project.emojiset.layout == Layout::Files {
+        return rename_standalone(&project.path, &file.path, written, new, is_alias, keep);
+    }
+
+    let svg = &file.svg;
+    let xml = roxmltree::Document::parse(svg).unwrap();
+    let group = xml
+        .descendants()
+        .find(|node| node.has_tag_name("g") && node.attribute("id") == Some(element_id(id)))
+        .ok_or_else(|| EditError::NotFound(old.into()))?;
+    let not_found = || EditError::NotFound(old.into());
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+
+    match document.definitions[id] {
+        source @ (MetadataSource::Desc | MetadataSource::Metadata) => {
+            let tag = if source == MetadataSource::Desc {
+                "desc"
+            } else {
+                "metadata"
+            };
+            let text = group_text(&xml, tag)
+                .remove(&group.range().start)
+                .and_then(|node| node.first_child())
+                .ok_or_else(not_found)?;
+            let definition = text.text().unwrap();
+            let mut edited =
+                rename_definition(definition, written, new, is_alias).ok_or_else(not_found)?;
+
+            if keep {
+                edited = add_alias(&edited, written);
+            }
+
+            edits.push((text.range(), escape_like(&svg[text.range()], &edited)));
+        }
+        MetadataSource::Attributes => {
+            let attribute = |name: &str| {
+                group
+                    .attributes()
+                    .iter()
+                    .find(|attribute| attribute.name() == name)
+            };
+            let name = attribute("data-emoji-name").ok_or_else(not_found)?;
+            let mut aliases: Vec<String> = attribute("data-emoji-aliases")
+                .map(|aliases| {
+                    aliases
+                        .value()
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|alias| !alias.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            if is_alias {
+                let alias = aliases
+                    .iter_mut()
+                    .find(|alias| *alias == written)
+                    .ok_or_else(not_found)?;
+
+                *alias = new.into();
+            } else {
+                edits.push((name.value_range(), escape_xml(new)));
+            }
+
+            if keep {
+                aliases.insert(0, written.into());
+            }
+
+            let aliases = escape_xml(&aliases.join(", "));
+
+            match attribute("data-emoji-aliases") {
+                Some(attribute) => edits.push((attribute.value_range(), aliases)),
+                None if keep => {
+                    // After the closing quote of the name
+                    let end = name.value_range().end + 1;
+
+                    edits.push((end..end, format!(r#" data-emoji-aliases="{}""#, aliases)));
+                }
+                None => {}
+            }
+        }
+        MetadataSource::Labels => {
+            if keep {
+                return Err(EditError::NoAliases);
+            }
+
+            let label = group
+                .attributes()
+                .iter()
+                .find(|attribute| {
+                    attribute.namespace() == Some(INKSCAPE) && attribute.name() == "label"
+                })
+                .ok_or_else(not_found)?;
+
+            edits.push((label.value_range(), escape_xml(&format!("emoji:{}", new))));
+        }
+    }
+
+    let mut svg = svg.clone();
+
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+
+    for (range, replacement) in edits {
+        svg.replace_range(range, &replacement);
+    }
+
+    Ok(vec![Change::Write(file.path.clone(), svg)])
+}
+
+/// Rename an emoji that is a file of its own. Names set in its toml are
+/// changed there, otherwise the file is moved along with its sidecar.
+fn rename_standalone(
+    root: &Path,
+    path: &Path,
+    old: &str,
+    new: &str,
+    is_alias: bool,
+    keep: bool,
+) -> Result<Vec<Change>, EditError> {
+    let not_found = || EditError::NotFound(old.into());
+    let sidecar = path.with_extension("toml");
+    let svg = std::fs::read_to_string(root.join(path)).map_err(|_| not_found())?;
+    let xml = roxmltree::Document::parse(&svg).map_err(|_| not_found())?;
+    let desc = xml
+        .root_element()
+        .children()
+        .find(|node| node.has_tag_name("desc"))
+        .and_then(|desc| desc.first_child());
+    let metadata = match std::fs::read_to_string(root.join(&sidecar)) {
+        Ok(metadata) => Some(metadata),
+        Err(_) => desc.and_then(|desc| desc.text()).map(String::from),
+    };
+    let original = metadata.clone().unwrap_or_default();
+    let named = toml::from_str::<toml::value::Table>(&original)
+        .is_ok_and(|table| table.contains_key("name"));
+    let mut edited = original.clone();
+
+    if is_alias || named {
+        edited = rename_definition(&edited, old, new, is_alias).ok_or_else(not_found)?;
+    }
+
+    if keep {
+        edited = add_alias(&edited, old);
+    }
+
+    let mut changes = Vec::new();
+    let in_sidecar = metadata.is_none() || root.join(&sidecar).exists();
+
+    if edited != original {
+        changes.push(match (in_sidecar, desc) {
+            (false, Some(desc)) => {
+                let mut svg = svg.clone();
+
+                let escaped = escape_like(&svg[desc.range()], &edited);
+
+                svg.replace_range(desc.range(), &escaped);
+
+                Change::Write(path.to_path_buf(), svg)
+            }
+            _ => Change::Write(sidecar.clone(), edited.clone()),
+        });
+    }
+
+    if !is_alias && !named {
+        let moved = path.with_file_name(format!("{}.svg", new));
+
+        if root.join(&moved).exists() {
+            return Err(EditError::NameTaken(new.into()));
+        }
+
+        changes.push(Change::Move(path.to_path_buf(), moved.clone()));
+
+        if root.join(&sidecar).exists() || (in_sidecar && edited != original) {
+            changes.push(Change::Move(sidecar, moved.with_extension("toml")));
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Languages emoji definitions are written in
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Syntax {
+    Toml,
+    Json,
+}
+
+lazy_static! {
+    static ref TOML_NAME_RE: Regex =
+        Regex::new(r#"(?m)^[ \t]*name[ \t]*=[ \t]*(?P<value>"(?:[^"\\\n]|\\.)*"|'[^'\n]*')"#)
+            .unwrap();
+    static ref TOML_ALIASES_RE: Regex =
+        Regex::new(r#"(?m)^[ \t]*aliases[ \t]*=[ \t]*\[(?P<value>[^\]]*)\]"#).unwrap();
+    static ref JSON_NAME_RE: Regex =
+        Regex::new(r#""name"\s*:\s*(?P<value>"(?:[^"\\]|\\.)*")"#).unwrap();
+    static ref JSON_ALIASES_RE: Regex =
+        Regex::new(r#""aliases"\s*:\s*\[(?P<value>[^\]]*)\]"#).unwrap();
+    static ref STRING_RE: Regex = Regex::new(r#""(?:[^"\\\n]|\\.)*"|'[^'\n]*'"#).unwrap();
+}
+
+impl Syntax {
+    fn of(definition: &str) -> Self {
+        if definition.trim_start().starts_with('{') {
+            Syntax::Json
+        } else {
+            Syntax::Toml
+        }
+    }
+
+    fn name(self) -> &'static Regex {
+        match self {
+            Syntax::Toml => &TOML_NAME_RE,
+            Syntax::Json => &JSON_NAME_RE,
+        }
+    }
+
+    fn aliases(self) -> &'static Regex {
+        match self {
+            Syntax::Toml => &TOML_ALIASES_RE,
+            Syntax::Json => &JSON_ALIASES_RE,
+        }
+    }
+
+    fn quote(self, text: &str) -> String {
+        match self {
+            Syntax::Toml => toml::Value::String(text.into()).to_string(),
+            Syntax::Json => serde_json::to_string(text).unwrap(),
+        }
+    }
+
+    fn parse(self, literal: &str) -> Option<String> {
+        match self {
+            Syntax::Toml => toml::from_str::<toml::value::Table>(&format!("value = {}", literal))
+                .ok()?
+                .remove("value")?
+                .as_str()
+                .map(String::from),
+            Syntax::Json => serde_json::from_str(literal).ok(),
+        }
+    }
+}
+
+/// Change the name, or an alias, in a definition, keeping the rest of it as it was
+fn rename_definition(definition: &str, old: &str, new: &str, is_alias: bool) -> Option<String> {
+    let syntax = Syntax::of(definition);
+    let mut definition = definition.to_string();
+    let range = if is_alias {
+        let aliases = syntax.aliases().captures(&definition)?.name("value")?;
+        let alias = STRING_RE
+            .find_iter(aliases.as_str())
+            .find(|alias| syntax.parse(alias.as_str()).as_deref() == Some(old))?;
+
+        aliases.start() + alias.start()..aliases.start() + alias.end()
+    } else {
+        syntax.name().captures(&definition)?.name("value")?.range()
+    };
+
+    definition.replace_range(range, &syntax.quote(new));
+
+    Some(definition)
+}
+
+/// Add an alias to the start of the aliases in a definition, adding them if there are none
+fn add_alias(definition: &str, alias: &str) -> String {
+    let syntax = Syntax::of(definition);
+    let mut definition = definition.to_string();
+    let quoted = syntax.quote(alias);
+    let aliases = syntax
+        .aliases()
+        .captures(&definition)
+        .and_then(|captures| captures.name("value"));
+    let name = syntax
+        .name()
+        .captures(&definition)
+        .and_then(|captures| captures.name("value"));
+
+    if let Some(aliases) = aliases {
+        let content = aliases.as_str();
+        let start = aliases.start() + content.len() - content.trim_start().len();
+
+        if content.trim().is_empty() {
+            definition.replace_range(aliases.range(), &quoted);
+        } else {
+            definition.insert_str(start, &format!("{}, ", quoted));
+        }
+    } else if let Some(name) = name {
+        let entry = match syntax {
+            Syntax::Toml => format!("\naliases = [{}]", quoted),
+            Syntax::Json => format!(", \"aliases\": [{}]", quoted),
+        };
+
+        definition.insert_str(name.end(), &entry);
+    } else {
+        if !definition.is_empty() && !definition.ends_with('\n') {
+            definition.push('\n');
+        }
+
+        definition.push_str(&format!("aliases = [{}]\n", quoted));
+    }
+
+    definition
+}
+
+/// Escape an edited definition the way the original text was escaped
+fn escape_like(original: &str, text: &str) -> String {
+    let escaped = escape_xml(text);
+
+    if original.contains("&quot;") {
+        escaped
+    } else {
+        escaped.replace("&quot;", "\"")
+    }
+}
+
+#[derive(Debug)]
+pub enum EditError {
+    /// None of the accepted metadata sources can be written to the file
+    NoSource,
+    NameTaken(String),
+    InvalidName(String, &'static str),
+    UnknownName(String),
+    /// The definition of the emoji couldn't be changed
+    NotFound(String),
+    /// Inkscape labels don't have aliases
+    NoAliases,
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use EditError::*;
+
+        match self {
+            NoSource => write!(
+                f,
+                "emoji can't be defined in this file, labels need the inkscape namespace to be declared"
+            ),
+            NameTaken(name) => write!(f, "an emoji named \"{}\" already exists", name),
+            InvalidName(name, reason) => write!(f, "the name \"{}\" {}", name, reason),
+            UnknownName(name) => write!(f, "there's no emoji named \"{}\"", name),
+            NotFound(name) => write!(
+                f,
+                "couldn't find where \"{}\" is named in its definition, rename it by hand",
+                name
+            ),
+            NoAliases => write!(
+                f,
+                "emoji defined by labels can't have aliases, use another metadata source to keep the old name"
+            ),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use crate::editor::Placement;
+use std::path::Path;
+
+/// An emoji read from a file, to be embedded in the document
+pub enum Import {
+    /// A still image, embedded as it is
+    Image {
+        mime: &'static str,
+        data: Vec<u8>,
+        width: f64,
+        height: f64,
+    },
+    /// The frames of an animation as png, with their delays in ms
+    Animation {
+        width: f64,
+        height: f64,
+        frames: Vec<(Vec<u8>, usize)>,
+    },
+}
+
+impl Import {
+    /// Read a png, svg, gif or webp file. Gifs and webps with a single frame
+    /// are images, they're converted to png as they can't always be embedded.
+    pub fn read(path: &Path) -> Result<Self, ImportError> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        let data = std::fs::read(path).map_err(|e| ImportError::Read(e.to_string()))?;
+        let decode = |e: &dyn std::fmt::Display| ImportError::Decode(e.to_string());
+
+        let (width, height, frames) = match extension.as_str() {
+            "png" => {
+                let decoder = png::Decoder::new(data.as_slice());
+                let reader = decoder.read_info().map_err(|e| decode(&e))?;
+                let info = reader.info();
+
+                return Ok(Import::Image {
+                    mime: "image/png",
+                    width: info.width as f64,
+                    height: info.height as f64,
+                    data,
+                });
+            }
+            "svg" => {
+                let opt = usvg::Options::default();
+                let tree = usvg::Tree::from_data(&data, &opt.to_ref()).map_err(|e| decode(&e))?;
+                let size = tree.svg_node().size;
+
+                return Ok(Import::Image {
+                    mime: "image/svg+xml",
+                    width: size.width(),
+                    height: size.height(),
+                    data,
+                });
+            }
+            "gif" => decode_gif(&data)?,
+            "webp" => decode_webp(&data)?,
+            _ => return Err(ImportError::Unsupported(extension)),
+        };
+
+        let (width, height) = (width as f64, height as f64);
+
+        Ok(match frames.len() {
+            0 => return Err(ImportError::Decode("the file has no frames".into())),
+            1 => Import::Image {
+                mime: "image/png",
+                data: frames.into_iter().next().unwrap().0,
+                width,
+                height,
+            },
+            _ => Import::Animation {
+                width,
+                height,
+                frames,
+            },
+        })
+    }
+
+    pub fn size(&self) -> (f64, f64) {
+        match self {
+            Import::Image { width, height, .. } | Import::Animation { width, height, .. } => {
+                (*width, *height)
+            }
+        }
+    }
+}
+
+/// An `<image>` of embedded data, fit to the middle of its cell
+pub fn embed(
+    mime: &str,
+    data: &[u8],
+    size: (f64, f64),
+    placement: Placement,
+    href: &str,
+) -> String {
+    let (width, height) = size;
+    let scale = (placement.size / width).min(placement.size / height);
+    let (width, height) = (width * scale, height * scale);
+
+    format!(
+        r#"<image x="{}" y="{}" width="{}" height="{}" {}="data:{};base64,{}" />"#,
+        placement.x + (placement.size - width) / 2.0,
+        placement.y + (placement.size - height) / 2.0,
+        width,
+        height,
+        href,
+        mime,
+        base64::encode(data)
+    )
+}
+
+/// Width and height of an animation, with its frames as png and their delays
+type Frames = (u32, u32, Vec<(Vec<u8>, usize)>);
+
+/// Frames of a gif drawn over each other as the gif is played
+fn decode_gif(data: &[u8]) -> Result<Frames, ImportError> {
+    let decode = |e: gif::DecodingError| ImportError::Decode(e.to_string());
+    let mut options = gif::DecodeOptions::new();
+
+    options.set_color_output(gif::ColorOutput::RGBA);
+
+    let mut decoder = options.read_info(data).map_err(decode)?;
+    let (width, height) = (decoder.width() as usize, decoder.height() as usize);
+    let mut canvas = vec![0; width * height * 4];
+    let mut frames = Vec::new();
+
+    while let Some(frame) = decoder.read_next_frame().map_err(decode)? {
+        let previous = canvas.clone();
+        let (left, top) = (frame.left as usize, frame.top as usize);
+        let (frame_width, frame_height) = (frame.width as usize, frame.height as usize);
+        let pixels =
+            move || (0..frame_height).flat_map(move |y| (0..frame_width).map(move |x| (x, y)));
+
+        for (x, y) in pixels() {
+            let source = (y * frame_width + x) * 4;
+            let (x, y) = (left + x, top + y);
+
+            if x >= width || y >= height || frame.buffer[source + 3] == 0 {
+                continue;
+            }
+
+            let target = (y * width + x) * 4;
+
+            canvas[target..target + 4].copy_from_slice(&frame.buffer[source..source + 4]);
+        }
+
+        // Browsers play very short delays at 100ms, so they were made for that:
+        let delay = match frame.delay {
+            0 | 1 => 100,
+            delay => delay as usize * 10,
+        };
+
+        frames.push((encode_png(&canvas, width as u32, height as u32), delay));
+
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                for (x, y) in pixels() {
+                    let (x, y) = (left + x, top + y);
+
+                    if x < width && y < height {
+                        let target = (y * width + x) * 4;
+
+                        canvas[target..target + 4].copy_from_slice(&[0; 4]);
+                    }
+                }
+            }
+            gif::DisposalMethod::Previous => canvas = previous,
+            _ => {}
+        }
+    }
+
+    Ok((width as u32, height as u32, frames))
+}
+
+fn decode_webp(data: &[u8]) -> Result<Frames, ImportError> {
+    let decoder =
+        webp_animation::Decoder::new(data).map_err(|e| ImportError::Decode(format!("{:?}", e)))?;
+    let (width, height) = decoder.dimensions();
+    let mut start = 0;
+    let frames = decoder
+        .into_iter()
+        .map(|frame| {
+            // Timestamps are when each frame ends
+            let delay = (frame.timestamp() - start).max(0) as usize;
+
+            start = frame.timestamp();
+
+            (encode_png(frame.data(), width, height), delay)
+        })
+        .collect();
+
+    Ok((width, height, frames))
+}
+
+fn encode_png(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(rgba))
+        .unwrap();
+
+    png
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Unsupported(String),
+    Read(String),
+    Decode(String),
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use ImportError::*;
+
+        match self {
+            Unsupported(extension) => write!(
+                f,
+                "\"{}\" files can't be imported, use png, svg, gif or webp",
+                extension
+            ),
+            Read(error) => write!(f, "error reading the file: {}", error),
+            Decode(error) => write!(f, "error decoding the file: {}", error),
+        }
+    }
+}
@@ -1,9 +1,17 @@
+pub mod cache;
 pub mod document;
+pub mod editor;
+pub mod import;
 pub mod manifest;
+pub mod outputs;
 pub mod renderer;
 
 pub mod prelude {
+    pub use crate::cache::*;
     pub use crate::document::*;
+    pub use crate::editor::*;
+    pub use crate::import::*;
     pub use crate::manifest::*;
+    pub use crate::outputs::*;
     pub use crate::renderer::*;
 }
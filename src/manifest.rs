@@ -1,30 +1,534 @@
+use crate::document::{invalid_name, Emoji};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::path::{Component, Path};
 use std::{path::PathBuf, str::FromStr};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Project {
     #[serde(skip)]
     pub path: PathBuf,
     pub emojiset: Emojiset,
-    #[serde(rename = "template", alias = "templates")]
+    #[serde(
+        rename = "template",
+        alias = "templates",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
     pub templates: Vec<Template>,
     #[serde(rename = "theme", alias = "themes")]
     pub themes: Vec<Theme>,
     #[serde(rename = "output", alias = "outputs")]
     pub outputs: Vec<Output>,
+    #[serde(
+        rename = "preview",
+        alias = "previews",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub previews: Vec<Preview>,
+    #[serde(
+        rename = "gallery",
+        alias = "galleries",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub galleries: Vec<Gallery>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Emojiset {
     pub name: String,
-    pub document: PathBuf,
+    /// Svg files that contain the emoji, a path, a directory, a glob or a list of them
+    pub document: DocumentPath,
+    /// Whether each svg file holds many emoji, or is an emoji of its own
+    #[serde(default, skip_serializing_if = "Layout::is_document")]
+    pub layout: Layout,
     pub stylesheet: PathBuf,
+    /// What to do with emoji names that can't be used in filenames
+    #[serde(default, skip_serializing_if = "NamePolicy::is_reject")]
+    pub names: NamePolicy,
+    /// Where emoji are defined in the document, in order of preference
+    #[serde(
+        default = "MetadataSource::defaults",
+        skip_serializing_if = "MetadataSource::is_defaults"
+    )]
+    pub metadata: Vec<MetadataSource>,
+}
+
+impl Emojiset {
+    /// Paths of every svg file of the emojiset, in the order they're listed.
+    /// Globs are expanded and sorted, other paths are kept even if they're missing.
+    /// Files listed more than once are only kept the first time.
+    pub fn documents(&self, root: &Path) -> Vec<PathBuf> {
+        let mut seen = HashSet::new();
+
+        self.document
+            .paths()
+            .iter()
+            .flat_map(|path| expand(root, path))
+            .filter(|path| seen.insert(path.canonicalize().unwrap_or_else(|_| path.clone())))
+            .collect()
+    }
+
+    /// Globs and directories of the emojiset that don't match any files
+    pub fn unmatched(&self, root: &Path) -> Vec<PathBuf> {
+        self.document
+            .paths()
+            .into_iter()
+            .filter(|path| expand(root, path).is_empty())
+            .collect()
+    }
+
+    /// Whether a file is, or would be, one of the svg files of the emojiset,
+    /// or the sidecar of one when each file is an emoji
+    pub fn is_document(&self, root: &Path, file: &Path) -> bool {
+        if self.layout == Layout::Files
+            && file
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+        {
+            return self.is_document(root, &file.with_extension("svg"));
+        }
+
+        self.document.paths().iter().any(|path| {
+            let mut path = root.join(path);
+
+            if path.is_dir() {
+                path = path.join("*.svg");
+            }
+
+            path == file
+                || glob::Pattern::new(&path.to_string_lossy())
+                    .is_ok_and(|pattern| pattern.matches_path(file))
+        })
+    }
+}
+
+/// Files a document path refers to, directories stand for the svg files in them
+fn expand(root: &Path, path: &Path) -> Vec<PathBuf> {
+    let mut path = root.join(path);
+
+    if path.is_dir() {
+        path = path.join("*.svg");
+    }
+
+    let pattern = path.to_string_lossy();
+
+    if !pattern.contains(['*', '?', '[']) {
+        return vec![path];
+    }
+
+    let mut matches: Vec<_> = glob::glob(&pattern)
+        .unwrap_or_else(|e| panic!("invalid emojiset document glob: {}", e))
+        .flatten()
+        .filter(|path| path.is_file())
+        .collect();
+
+    matches.sort();
+    matches
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    /// Emoji are groups with a toml description, any number to a file
+    #[default]
+    Document,
+    /// Every file is one emoji, named after the file
+    Files,
+}
+
+impl Layout {
+    fn is_document(&self) -> bool {
+        self == &Layout::Document
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum DocumentPath {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+impl DocumentPath {
+    pub fn paths(&self) -> Vec<PathBuf> {
+        match self {
+            DocumentPath::One(path) => vec![path.clone()],
+            DocumentPath::Many(paths) => paths.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataSource {
+    /// Toml in the `<desc>` of a group
+    Desc,
+    /// `data-emoji-*` attributes on a group
+    Attributes,
+    /// Inkscape labels like `emoji:bunne` or `frame:2@60ms`
+    Labels,
+    /// Json or toml in the `<metadata>` of a group
+    Metadata,
+}
+
+impl MetadataSource {
+    fn defaults() -> Vec<Self> {
+        vec![MetadataSource::Desc]
+    }
+
+    fn is_defaults(sources: &[Self]) -> bool {
+        sources == Self::defaults()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NamePolicy {
+    /// Fail to load the document
+    #[default]
+    Reject,
+    /// Replace characters that can't be used with underscores
+    Replace,
+    /// Convert names to ascii, then replace characters that can't be used
+    Transliterate,
+}
+
+impl NamePolicy {
+    fn is_reject(&self) -> bool {
+        self == &NamePolicy::Reject
+    }
+}
+
+impl Project {
+    /// Check that output path patterns are valid, stay inside their output
+    /// directories and give every exported file its own path
+    pub fn check_paths(&self, emojis: &[Emoji]) -> Result<(), OutputPath> {
+        let mut paths = HashMap::new();
+
+        for output in &self.outputs {
+            let pattern = output.pattern();
+
+            if Path::new(pattern)
+                .components()
+                .any(|component| !matches!(component, Component::Normal(_)))
+            {
+                return Err(OutputPath::OutsideDirectory {
+                    pattern: pattern.into(),
+                });
+            }
+
+            for theme in &self.themes {
+                for emoji in emojis {
+                    for name in emoji.names() {
+                        let exported = output.name(theme, &name);
+
+                        if let Some(reason) = invalid_name(&exported) {
+                            return Err(OutputPath::InvalidName {
+                                name,
+                                exported,
+                                reason,
+                            });
+                        }
+
+                        for extension in output.extensions(emoji) {
+                            let expanded = output.expand(theme, emoji, &name, &extension)?;
+                            let path = output.directory.join(&expanded);
+                            let owner = format!("{} ({} theme)", name, theme.name);
+
+                            // Categories, prefixes and suffixes can hold separators too:
+                            if Path::new(&expanded)
+                                .components()
+                                .any(|component| !matches!(component, Component::Normal(_)))
+                            {
+                                return Err(OutputPath::Escapes { path, owner });
+                            }
+
+                            if let Some(first) = paths.insert(path.clone(), owner.clone()) {
+                                return Err(OutputPath::Collision {
+                                    path,
+                                    first,
+                                    second: owner,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Output {
     pub trim: bool,
     pub directory: PathBuf,
+    /// Pattern for the paths of exported files inside the directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Scale exported emoji to fit within this many pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u32>,
+    /// Changes made to emoji names for this output, after the theme's
+    #[serde(default, skip_serializing_if = "NameTransform::is_empty")]
+    pub names: NameTransform,
+    #[serde(default, skip_serializing_if = "OutputFormat::is_raster")]
+    pub format: OutputFormat,
+}
+
+impl Output {
+    pub const DEFAULT_PATH: &'static str = "{theme}/{prefix}{name}{suffix}.{ext}";
+
+    /// Name of an exported emoji once the theme and output name transforms are applied
+    pub fn name(&self, theme: &Theme, name: &str) -> String {
+        self.names.apply(&theme.names.apply(name))
+    }
+
+    /// Path of an exported emoji file for the theme
+    pub fn path(&self, theme: &Theme, emoji: &Emoji, name: &str, extension: &str) -> PathBuf {
+        let path = self
+            .expand(theme, emoji, name, extension)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        self.directory.join(path)
+    }
+
+    /// File extensions the emoji is exported with, animations exported as svg
+    /// get a file for each frame, like "bunnehop.1.svg"
+    pub fn extensions(&self, emoji: &Emoji) -> Vec<String> {
+        match (self.format, emoji) {
+            (OutputFormat::Svg, Emoji::Animation { .. }) => (1..=emoji.frames().len())
+                .map(|frame| format!("{}.svg", frame))
+                .collect(),
+            (OutputFormat::Svg, Emoji::Image { .. }) => vec!["svg".into()],
+            _ => emoji.extensions().iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    fn pattern(&self) -> &str {
+        self.path.as_deref().unwrap_or(Self::DEFAULT_PATH)
+    }
+
+    /// Replace the placeholders in the path pattern
+    fn expand(
+        &self,
+        theme: &Theme,
+        emoji: &Emoji,
+        name: &str,
+        extension: &str,
+    ) -> Result<String, OutputPath> {
+        let pattern = self.pattern();
+        let mut path = String::new();
+        let mut rest = pattern;
+
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| OutputPath::Unclosed {
+                    pattern: pattern.into(),
+                })?;
+            let placeholder = &rest[start + 1..end];
+
+            path.push_str(&rest[..start]);
+
+            match placeholder {
+                "theme" => path.push_str(&theme.name),
+                "prefix" => path.push_str(&theme.prefix),
+                "name" => path.push_str(&self.name(theme, name)),
+                "suffix" => path.push_str(&theme.suffix),
+                "category" => {
+                    path.push_str(&emoji.category().unwrap_or_else(|| "uncategorized".into()))
+                }
+                "ext" => path.push_str(extension),
+                "size" => match self.size {
+                    Some(size) => path.push_str(&size.to_string()),
+                    None => {
+                        return Err(OutputPath::NoSize {
+                            pattern: pattern.into(),
+                        })
+                    }
+                },
+                _ => {
+                    return Err(OutputPath::UnknownPlaceholder {
+                        pattern: pattern.into(),
+                        placeholder: placeholder.into(),
+                    })
+                }
+            }
+
+            rest = &rest[end + 1..];
+        }
+
+        path.push_str(rest);
+
+        Ok(path)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// Images as png, animations as webp and gif
+    #[default]
+    Raster,
+    /// Standalone svg files with the theme applied, cropped to each emoji
+    Svg,
+}
+
+impl OutputFormat {
+    fn is_raster(&self) -> bool {
+        self == &OutputFormat::Raster
+    }
+}
+
+#[derive(Debug)]
+pub enum OutputPath {
+    UnknownPlaceholder {
+        pattern: String,
+        placeholder: String,
+    },
+    Unclosed {
+        pattern: String,
+    },
+    NoSize {
+        pattern: String,
+    },
+    OutsideDirectory {
+        pattern: String,
+    },
+    Escapes {
+        path: PathBuf,
+        owner: String,
+    },
+    InvalidName {
+        name: String,
+        exported: String,
+        reason: &'static str,
+    },
+    Collision {
+        path: PathBuf,
+        first: String,
+        second: String,
+    },
+}
+
+impl std::fmt::Display for OutputPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use OutputPath::*;
+
+        match self {
+            UnknownPlaceholder {
+                pattern,
+                placeholder,
+            } => write!(
+                f,
+                "unknown placeholder {{{}}} in output path \"{}\", use {{theme}}, {{prefix}}, {{name}}, {{suffix}}, {{size}}, {{category}} or {{ext}}",
+                placeholder, pattern
+            ),
+            Unclosed { pattern } => write!(f, "unclosed placeholder in output path \"{}\"", pattern),
+            NoSize { pattern } => write!(
+                f,
+                "output path \"{}\" uses {{size}} but the output has no size",
+                pattern
+            ),
+            OutsideDirectory { pattern } => write!(
+                f,
+                "output path \"{}\" must be relative and stay inside the output directory",
+                pattern
+            ),
+            Escapes { path, owner } => write!(
+                f,
+                "{} would be exported to {}, outside the output directory",
+                owner,
+                path.display()
+            ),
+            InvalidName {
+                name,
+                exported,
+                reason,
+            } => write!(
+                f,
+                "{} is exported as \"{}\", which {}",
+                name, exported, reason
+            ),
+            Collision {
+                path,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} and {} would both be exported to {}, output paths must be unique",
+                first,
+                second,
+                path.display()
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Preview {
+    /// Directory the contact sheets are written to
+    pub directory: PathBuf,
+    /// Number of emoji in each row
+    #[serde(default = "Preview::default_columns")]
+    pub columns: usize,
+    /// Width and height of each emoji cell in pixels
+    #[serde(default = "Preview::default_size")]
+    pub size: u32,
+    /// Play animations in an animated webp instead of showing their first frame
+    #[serde(default)]
+    pub animated: bool,
+    /// Background colour of the light contact sheet
+    #[serde(default = "Preview::default_light")]
+    pub light: String,
+    /// Background colour of the dark contact sheet
+    #[serde(default = "Preview::default_dark")]
+    pub dark: String,
+}
+
+impl Preview {
+    fn default_columns() -> usize {
+        8
+    }
+
+    fn default_size() -> u32 {
+        128
+    }
+
+    fn default_light() -> String {
+        "#ffffff".into()
+    }
+
+    fn default_dark() -> String {
+        "#36393f".into()
+    }
+}
+
+impl Default for Preview {
+    fn default() -> Self {
+        Self {
+            directory: "preview".into(),
+            columns: Self::default_columns(),
+            size: Self::default_size(),
+            animated: false,
+            light: Self::default_light(),
+            dark: Self::default_dark(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Gallery {
+    /// Directory the gallery index.html is written to
+    pub directory: PathBuf,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -39,10 +543,92 @@ pub struct Theme {
     pub name: String,
     /// Prefix for exported filenames
     pub prefix: String,
+    /// Suffix for exported filenames
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub suffix: String,
+    /// Changes made to emoji names for this theme
+    #[serde(default, skip_serializing_if = "NameTransform::is_empty")]
+    pub names: NameTransform,
     /// Path to the theme css stylesheet
     pub stylesheet: PathBuf,
 }
 
+/// Changes made to emoji names before they're used in filenames
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct NameTransform {
+    /// Remove accents and other combining marks, "café" becomes "cafe"
+    #[serde(default)]
+    pub strip_diacritics: bool,
+    /// Split words with underscores, "BunneHop" and "bunne-hop" become "Bunne_Hop" and "bunne_hop"
+    #[serde(default)]
+    pub snake_case: bool,
+    /// Lowercase every letter, "BunneHop" becomes "bunnehop"
+    #[serde(default)]
+    pub lowercase: bool,
+    /// Longest a name can be in characters, longer names are cut short
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<NonZeroUsize>,
+}
+
+impl NameTransform {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    pub fn apply(&self, name: &str) -> String {
+        let mut name = name.to_string();
+
+        if self.strip_diacritics {
+            name = name
+                .nfd()
+                .filter(|c| !is_combining_mark(*c))
+                .nfc()
+                .collect();
+        }
+
+        if self.snake_case {
+            name = snake_case(&name);
+        }
+
+        if self.lowercase {
+            name = name.to_lowercase();
+        }
+
+        if let Some(max_length) = self.max_length {
+            name = name.chars().take(max_length.get()).collect();
+        }
+
+        name
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    let mut previous: Option<char> = None;
+
+    for c in name.chars() {
+        if c.is_whitespace() || c == '-' || c == '_' {
+            if !snake.is_empty() && !snake.ends_with('_') {
+                snake.push('_');
+            }
+        } else {
+            // Start a new word where a lowercase letter or digit is followed by a capital:
+            if c.is_uppercase()
+                && previous.is_some_and(|p| p.is_lowercase() || p.is_numeric())
+                && !snake.ends_with('_')
+            {
+                snake.push('_');
+            }
+
+            snake.push(c);
+        }
+
+        previous = Some(c);
+    }
+
+    snake.trim_end_matches('_').into()
+}
+
 #[derive(Debug)]
 pub enum ProjectPath {
     NoProject,
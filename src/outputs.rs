@@ -0,0 +1,121 @@
+use crate::document::Emoji;
+use crate::manifest::{Gallery, Output, Project, Theme};
+use crate::renderer::{gallery_renderer, preview_renderer};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{remove_dir, remove_file};
+use std::path::{Path, PathBuf};
+
+/// Files that builds have written to an output directory, stored in the
+/// directory so stale files can be removed without touching anything else
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BuildManifest {
+    pub files: BTreeSet<PathBuf>,
+}
+
+impl BuildManifest {
+    pub const FILE_NAME: &'static str = ".emoji-build.toml";
+
+    /// Read the manifest of an output directory, empty if there isn't one
+    pub fn load(directory: &Path) -> Self {
+        std::fs::read_to_string(directory.join(Self::FILE_NAME))
+            .ok()
+            .and_then(|data| toml::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, directory: &Path) {
+        let path = directory.join(Self::FILE_NAME);
+
+        if self.files.is_empty() {
+            let _ = remove_file(path);
+        } else {
+            std::fs::write(path, toml::to_string(self).unwrap())
+                .expect("error writing the build manifest");
+        }
+    }
+
+    /// Paths of every file the themes export to the output, relative to its directory
+    pub fn files(themes: &[Theme], emojis: &[Emoji], output: &Output) -> BTreeSet<PathBuf> {
+        let mut files = BTreeSet::new();
+
+        for theme in themes {
+            for emoji in emojis {
+                for name in emoji.names() {
+                    for extension in output.extensions(emoji) {
+                        let path = output.path(theme, emoji, &name, &extension);
+
+                        files.insert(path.strip_prefix(&output.directory).unwrap().to_path_buf());
+                    }
+                }
+            }
+        }
+
+        files
+    }
+
+    /// Paths of every file a build of the themes, emoji, outputs and galleries
+    /// writes, relative to the directory whose manifest keeps track of them.
+    /// Contact sheets are written for every theme, and directories are joined
+    /// to the project path.
+    pub fn tracked(
+        project: &Project,
+        themes: &[Theme],
+        emojis: &[Emoji],
+        outputs: &[Output],
+        galleries: &[Gallery],
+    ) -> BTreeMap<PathBuf, BTreeSet<PathBuf>> {
+        let mut tracked: BTreeMap<PathBuf, BTreeSet<PathBuf>> = BTreeMap::new();
+
+        for output in outputs {
+            tracked
+                .entry(project.path.join(&output.directory))
+                .or_default()
+                .extend(Self::files(themes, emojis, output));
+        }
+
+        for preview in &project.previews {
+            for theme in themes {
+                for path in preview_renderer::paths(&theme.name, preview) {
+                    tracked
+                        .entry(project.path.join(&preview.directory))
+                        .or_default()
+                        .insert(path.strip_prefix(&preview.directory).unwrap().to_path_buf());
+                }
+            }
+        }
+
+        for gallery in galleries {
+            let path = gallery_renderer::path(gallery);
+
+            tracked
+                .entry(project.path.join(&gallery.directory))
+                .or_default()
+                .insert(path.strip_prefix(&gallery.directory).unwrap().to_path_buf());
+        }
+
+        tracked
+    }
+
+    /// Delete the files written by earlier builds that aren't expected any more,
+    /// along with directories left empty, and return the paths that were removed
+    pub fn prune(&mut self, directory: &Path, expected: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
+        let stale: Vec<_> = self.files.difference(expected).cloned().collect();
+
+        for file in &stale {
+            let path = directory.join(file);
+
+            let _ = remove_file(&path);
+
+            for parent in path.ancestors().skip(1) {
+                if parent == directory || remove_dir(parent).is_err() {
+                    break;
+                }
+            }
+
+            self.files.remove(file);
+        }
+
+        stale.iter().map(|file| directory.join(file)).collect()
+    }
+}
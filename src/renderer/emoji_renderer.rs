@@ -1,12 +1,14 @@
-use crate::document::Emoji;
-use crate::manifest::{Output, Theme};
+use crate::document::{element_id, Document, Emoji};
+use crate::manifest::{Output, OutputFormat, Theme};
 use lazy_static::lazy_static;
 use rayon::prelude::*;
 use regex::Regex;
 use resvg::trim_transparency;
+use std::collections::{HashMap, HashSet};
 use std::fs::{create_dir_all, File};
-use tiny_skia::{IntRect, Pixmap};
-use usvg::{NodeExt, Tree};
+use std::path::Path;
+use tiny_skia::{IntRect, Pixmap, PixmapPaint, Transform};
+use usvg::{NodeExt, NodeKind, Tree};
 use webp_animation::prelude::*;
 
 #[derive(Clone, Debug)]
@@ -23,58 +25,152 @@ pub enum RenderableEmoji {
     },
 }
 
-/// Apply the theme to emoji and return renderable emoji
-pub fn process(svg: &str, theme: &Theme, emojis: &Vec<Emoji>) -> Vec<RenderableEmoji> {
-    // Replace emojiset stylesheet with theme stylesheet:
+impl RenderableEmoji {
+    pub fn emoji(&self) -> &Emoji {
+        match self {
+            RenderableEmoji::Image { emoji, .. } | RenderableEmoji::Animation { emoji, .. } => {
+                emoji
+            }
+        }
+    }
+}
+
+pub trait OnProgress: Fn(&Emoji, Option<&RenderableEmoji>) {}
+
+impl<T> OnProgress for T where T: Fn(&Emoji, Option<&RenderableEmoji>) {}
+
+/// Apply the theme to emoji, then rasterize each one and write it to every
+/// output before moving on. Outputs are written one after another, so a worker
+/// never starts on another emoji while it holds one, and the size of the rayon
+/// pool this runs in bounds memory use. Emoji are only rasterized when a raster
+/// output needs them or `rasterize` is set, `on_progress` gets None otherwise.
+pub fn render<F>(
+    document: &Document,
+    theme: &Theme,
+    emojis: &[Emoji],
+    outputs: &[Output],
+    rasterize: bool,
+    on_progress: F,
+) where
+    F: OnProgress + Sync + Send,
+{
+    let rasterize = rasterize
+        || outputs
+            .iter()
+            .any(|output| output.format == OutputFormat::Raster);
+
+    document
+        .group(emojis)
+        .par_iter()
+        .for_each(|(file, emojis)| {
+            let svg = apply_theme(&file.svg, theme);
+            let data = svg.as_bytes();
+
+            // Trees can't be shared between threads, so a copy of the themed file is
+            // parsed for each batch of emoji rayon splits the work into, and reused
+            // for every emoji in the batch:
+            emojis.par_iter().for_each_init(
+                || parse_tree(data),
+                |rtree, emoji| {
+                    let renderable = if rasterize {
+                        Some(process(emoji, rtree))
+                    } else {
+                        None
+                    };
+
+                    for output in outputs {
+                        match (output.format, &renderable) {
+                            (OutputFormat::Raster, Some(renderable)) => {
+                                render_emoji(renderable, rtree, theme, output)
+                            }
+                            (OutputFormat::Raster, None) => unreachable!("not rasterized"),
+                            (OutputFormat::Svg, _) => {
+                                let files = extract_emoji(emoji, rtree, output);
+
+                                render_svg(emoji, &files, theme, output);
+                            }
+                        }
+                    }
+
+                    on_progress(emoji, renderable.as_ref());
+                },
+            );
+        });
+}
+
+/// Replace the stylesheet of an emojiset file with the theme stylesheet
+fn apply_theme(svg: &str, theme: &Theme) -> String {
     lazy_static! {
         static ref STYLE_ELEMENT_RE: Regex = Regex::new(r"(?s:<style.*?>.*?</style>)").unwrap();
     }
 
-    let mut svg = svg.to_string();
     let path = theme.stylesheet.canonicalize().unwrap();
     let css = std::fs::read_to_string(path).unwrap();
 
-    svg = STYLE_ELEMENT_RE
-        .replace(&svg, format!("<style>{}</style>", css))
-        .to_string();
-
-    let data = svg.as_bytes();
+    STYLE_ELEMENT_RE
+        .replace(svg, format!("<style>{}</style>", css))
+        .to_string()
+}
 
-    emojis
-        .par_iter()
-        .map(|emoji| process_emoji(emoji, data))
-        .collect()
+/// Rasterize an emoji, or each frame of an animation, from a parsed document
+pub fn process(emoji: &Emoji, rtree: &Tree) -> RenderableEmoji {
+    match emoji {
+        Emoji::Image { .. } => process_image(emoji, rtree),
+        Emoji::Animation { .. } => process_animation(emoji, rtree),
+        _ => unreachable!("should not be able to reach this"),
+    }
 }
 
-fn process_emoji(emoji: &Emoji, data: &[u8]) -> RenderableEmoji {
+pub fn parse_tree(data: &[u8]) -> Tree {
     let opt = usvg::Options {
         keep_named_groups: true,
         ..Default::default()
     };
 
-    let rtree = usvg::Tree::from_data(data, &opt.to_ref()).unwrap();
+    usvg::Tree::from_data(data, &opt.to_ref()).unwrap()
+}
 
-    match emoji {
-        Emoji::Image { .. } => process_image(emoji, rtree),
-        Emoji::Animation { .. } => process_animation(emoji, rtree),
-        _ => unreachable!("should not be able to reach this"),
+/// Measure the unscaled, untrimmed size of each emoji without rendering them
+pub fn measure(document: &Document, emojis: &[Emoji]) -> Vec<(usize, usize)> {
+    let mut sizes = HashMap::new();
+
+    for (file, emojis) in document.group(emojis) {
+        let rtree = parse_tree(file.svg.as_bytes());
+
+        for emoji in emojis {
+            let ids = match emoji {
+                Emoji::Animation { .. } => emoji.frames().iter().map(|f| f.id().unwrap()).collect(),
+                _ => vec![emoji.id().unwrap()],
+            };
+            let size = ids.iter().fold((0, 0), |(width, height), id| {
+                let bbox = find_node(&rtree, &emoji, id).calculate_bbox().unwrap();
+
+                (
+                    width.max(bbox.width() as usize),
+                    height.max(bbox.height() as usize),
+                )
+            });
+
+            sizes.insert(emoji.id().unwrap(), size);
+        }
     }
-}
 
-fn process_image(emoji: &Emoji, rtree: Tree) -> RenderableEmoji {
-    let node = rtree.node_by_id(&emoji.id().unwrap()).unwrap();
-    let bbox = node.calculate_bbox().unwrap();
-    let mut pixmap = Pixmap::new(bbox.width() as u32, bbox.height() as u32).unwrap();
+    emojis
+        .iter()
+        .map(|emoji| sizes[&emoji.id().unwrap()])
+        .collect()
+}
 
-    resvg::render_node(&rtree, &node, usvg::FitTo::Size(326, 326), pixmap.as_mut()).unwrap();
+fn process_image(emoji: &Emoji, rtree: &Tree) -> RenderableEmoji {
+    let node = find_node(rtree, emoji, &emoji.id().unwrap());
 
     RenderableEmoji::Image {
         emoji: emoji.clone(),
-        pixmap,
+        pixmap: rasterize(rtree, &node, 1.0),
     }
 }
 
-fn process_animation(emoji: &Emoji, rtree: Tree) -> RenderableEmoji {
+fn process_animation(emoji: &Emoji, rtree: &Tree) -> RenderableEmoji {
     let mut width: f64 = 0.0;
     let mut height: f64 = 0.0;
 
@@ -88,9 +184,8 @@ fn process_animation(emoji: &Emoji, rtree: Tree) -> RenderableEmoji {
                 position,
             } = &frame
             {
-                let node = rtree.node_by_id(id).unwrap();
+                let node = find_node(rtree, emoji, id);
                 let bbox = node.calculate_bbox().unwrap();
-                let mut pixmap = Pixmap::new(bbox.width() as u32, bbox.height() as u32).unwrap();
 
                 if bbox.width() > width {
                     width = bbox.width();
@@ -100,10 +195,7 @@ fn process_animation(emoji: &Emoji, rtree: Tree) -> RenderableEmoji {
                     height = bbox.height();
                 }
 
-                resvg::render_node(&rtree, &node, usvg::FitTo::Size(326, 326), pixmap.as_mut())
-                    .unwrap();
-
-                (*position, *delay, pixmap)
+                (*position, *delay, rasterize(rtree, &node, 1.0))
             } else {
                 unreachable!("not a frame");
             }
@@ -118,36 +210,217 @@ fn process_animation(emoji: &Emoji, rtree: Tree) -> RenderableEmoji {
     }
 }
 
-pub trait OnProgress<'a>: Fn(&'a Emoji) {}
+/// The node of an emoji or one of its frames. Groups with nothing to draw are
+/// left out of the tree, so they're reported by the name of their emoji.
+fn find_node(rtree: &Tree, emoji: &Emoji, id: &str) -> usvg::Node {
+    rtree
+        .node_by_id(element_id(id))
+        .filter(|node| node.calculate_bbox().is_some())
+        .unwrap_or_else(|| {
+            panic!(
+                "emoji \"{}\" has nothing to draw in \"{}\", add some shapes to it",
+                emoji.name().unwrap(),
+                id
+            )
+        })
+}
 
-impl<'a, T> OnProgress<'a> for T where T: Fn(&'a Emoji) {}
+/// Rasterize a node at a zoom level, into a pixmap as big as its zoomed box
+fn rasterize(rtree: &Tree, node: &usvg::Node, zoom: f32) -> Pixmap {
+    let bbox = node.calculate_bbox().unwrap();
+    let (width, height) = (bbox.width() * zoom as f64, bbox.height() * zoom as f64);
+    let mut pixmap = Pixmap::new((width as u32).max(1), (height as u32).max(1)).unwrap();
 
-/// Render emoji and write them to disk
-pub fn render<'a, F>(
-    emojis: &'a Vec<RenderableEmoji>,
-    theme: &Theme,
-    output: &Output,
-    on_progress: F,
-) where
-    F: OnProgress<'a> + Sync + Send,
-{
-    emojis.par_iter().for_each(|emoji| {
-        render_emoji(emoji, theme, output, &on_progress);
+    resvg::render_node(rtree, node, usvg::FitTo::Zoom(zoom), pixmap.as_mut()).unwrap();
+
+    pixmap
+}
+
+/// Read an emoji back from the first of the raster outputs that has its files,
+/// so it can be drawn again without rendering it. None if no output has them.
+pub fn load(emoji: &Emoji, theme: &Theme, outputs: &[Output]) -> Option<RenderableEmoji> {
+    let name = emoji.name().unwrap();
+
+    outputs
+        .iter()
+        .filter(|output| output.format == OutputFormat::Raster)
+        .find_map(|output| match emoji {
+            Emoji::Image { .. } => Some(RenderableEmoji::Image {
+                emoji: emoji.clone(),
+                pixmap: Pixmap::load_png(output.path(theme, emoji, &name, "png")).ok()?,
+            }),
+            _ => {
+                let data = std::fs::read(output.path(theme, emoji, &name, "webp")).ok()?;
+                let decoder = Decoder::new(&data).ok()?;
+                let (width, height) = decoder.dimensions();
+                let mut start = 0;
+                let frames = decoder
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, frame)| {
+                        let mut pixmap = Pixmap::new(width, height).unwrap();
+                        // Timestamps are when each frame ends
+                        let delay = (frame.timestamp() - start).max(0) as usize;
+
+                        start = frame.timestamp();
+                        pixmap.data_mut().copy_from_slice(frame.data());
+
+                        (index + 1, delay, pixmap)
+                    })
+                    .collect();
+
+                Some(RenderableEmoji::Animation {
+                    emoji: emoji.clone(),
+                    width: width as usize,
+                    height: height as usize,
+                    frames,
+                })
+            }
+        })
+}
+
+/// Copy an emoji, or each frame of an animation, into a standalone svg along
+/// with the definitions it uses. Parsing has already applied the theme styles
+/// as attributes and left out anything that isn't svg, like editor namespaces.
+fn extract_emoji(emoji: &Emoji, rtree: &Tree, output: &Output) -> Vec<String> {
+    let ids = match emoji {
+        Emoji::Animation { .. } => emoji.frames().iter().map(|f| f.id().unwrap()).collect(),
+        _ => vec![emoji.id().unwrap()],
+    };
+    let nodes: Vec<_> = ids.iter().map(|id| find_node(rtree, emoji, id)).collect();
+    let boxes: Vec<_> = nodes
+        .iter()
+        .map(|node| {
+            node.calculate_bbox()
+                .and_then(|bbox| bbox.to_rect())
+                .unwrap_or(rtree.svg_node().view_box.rect)
+        })
+        .collect();
+
+    // Like rasterized frames, each frame starts at its own box and all of them
+    // are as big as the largest, so they're the same size:
+    let width = boxes.iter().map(|rect| rect.width()).fold(0.0, f64::max);
+    let height = boxes.iter().map(|rect| rect.height()).fold(0.0, f64::max);
+    let scale = match output.size {
+        Some(size) => size as f64 / width.max(height),
+        None => 1.0,
+    };
+
+    nodes
+        .iter()
+        .zip(&boxes)
+        .map(|(node, rect)| {
+            let rect = usvg::Rect::new(rect.x(), rect.y(), width, height).unwrap();
+            let tree = Tree::create(usvg::Svg {
+                size: usvg::Size::new(width * scale, height * scale).unwrap(),
+                view_box: usvg::ViewBox {
+                    rect,
+                    aspect: usvg::AspectRatio::default(),
+                },
+            });
+            let used = references(rtree, node);
+
+            for definition in rtree.defs().children() {
+                if used.contains(&*definition.id()) {
+                    tree.defs().append(definition.clone().make_deep_copy());
+                }
+            }
+
+            let mut parent = tree.root();
+            let transform = node.abs_transform();
+
+            // Keep the transforms of the groups the emoji is in:
+            if !transform.is_default() {
+                parent = parent.append_kind(NodeKind::Group(usvg::Group {
+                    transform,
+                    ..Default::default()
+                }));
+            }
+
+            parent.append(node.clone().make_deep_copy());
+
+            clean_svg(&tree.to_string(&usvg::XmlOptions::default()))
+        })
+        .collect()
+}
+
+/// Write opaque colours as hex and leave out empty definitions
+fn clean_svg(svg: &str) -> String {
+    lazy_static! {
+        static ref OPAQUE_COLOR_RE: Regex = Regex::new(r"rgba\((\d+),(\d+),(\d+),255\)").unwrap();
+        static ref EMPTY_DEFS_RE: Regex = Regex::new(r"(?m)^\s*<defs/>\n").unwrap();
+    }
+
+    let svg = OPAQUE_COLOR_RE.replace_all(svg, |captures: &regex::Captures| {
+        let channel = |index: usize| captures[index].parse::<u8>().unwrap();
+
+        format!("#{:02x}{:02x}{:02x}", channel(1), channel(2), channel(3))
     });
+
+    EMPTY_DEFS_RE.replace(&svg, "").to_string()
 }
 
-pub fn render_emoji<'a, F>(
-    emoji: &'a RenderableEmoji,
-    theme: &Theme,
-    output: &Output,
-    on_progress: F,
-) where
-    F: OnProgress<'a>,
-{
+/// Ids of the definitions a node uses, including those used by other definitions
+fn references(rtree: &Tree, node: &usvg::Node) -> HashSet<String> {
+    let mut used = HashSet::new();
+    let mut pending = vec![node.clone()];
+
+    while let Some(node) = pending.pop() {
+        for node in node.descendants() {
+            let mut ids = Vec::new();
+            let mut paint = |paint: Option<&usvg::Paint>| {
+                if let Some(usvg::Paint::Link(id)) = paint {
+                    ids.push(id.clone());
+                }
+            };
+
+            match &*node.borrow() {
+                NodeKind::Path(path) => {
+                    paint(path.fill.as_ref().map(|fill| &fill.paint));
+                    paint(path.stroke.as_ref().map(|stroke| &stroke.paint));
+                }
+                NodeKind::Group(group) => {
+                    paint(group.filter_fill.as_ref());
+                    paint(group.filter_stroke.as_ref());
+                    ids.extend(group.clip_path.iter().chain(&group.mask).cloned());
+                    ids.extend(group.filter.iter().cloned());
+                }
+                NodeKind::ClipPath(clip) => ids.extend(clip.clip_path.clone()),
+                NodeKind::Mask(mask) => ids.extend(mask.mask.clone()),
+                _ => {}
+            }
+
+            for id in ids {
+                if let Some(definition) = rtree.defs_by_id(&id) {
+                    if used.insert(id) {
+                        pending.push(definition);
+                    }
+                }
+            }
+        }
+    }
+
+    used
+}
+
+/// Write the extracted svg files of an emoji to disk
+pub fn render_svg(emoji: &Emoji, files: &[String], theme: &Theme, output: &Output) {
+    for (extension, svg) in output.extensions(emoji).iter().zip(files) {
+        let path = output.path(theme, emoji, &emoji.name().unwrap(), extension);
+
+        create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, svg).unwrap();
+
+        copy_aliases(emoji, &path, theme, output, extension);
+    }
+}
+
+/// Write a rasterized emoji to disk. Outputs with a size render the emoji
+/// again at that size, so it's as sharp as when it's exported at its own size.
+pub fn render_emoji(emoji: &RenderableEmoji, rtree: &Tree, theme: &Theme, output: &Output) {
     match emoji {
         RenderableEmoji::Image { emoji, pixmap } => {
-            render_image(emoji, pixmap, theme, output);
-            on_progress(emoji);
+            render_image(emoji, pixmap, rtree, theme, output);
         }
         RenderableEmoji::Animation {
             emoji,
@@ -155,46 +428,65 @@ pub fn render_emoji<'a, F>(
             height,
             frames,
         } => {
-            render_animation(emoji, *width, *height, frames, theme, output);
+            render_animation(emoji, *width, *height, frames, rtree, theme, output);
         }
     }
 }
 
-pub fn render_image(emoji: &Emoji, pixmap: &Pixmap, theme: &Theme, output: &Output) {
-    let dir = output.directory.join(&theme.name);
-    let path = dir.join(format!("{}{}.png", theme.prefix, emoji.name().unwrap()));
+pub fn render_image(emoji: &Emoji, pixmap: &Pixmap, rtree: &Tree, theme: &Theme, output: &Output) {
+    let path = output.path(theme, emoji, &emoji.name().unwrap(), "png");
 
-    create_dir_all(&dir).unwrap();
+    create_dir_all(path.parent().unwrap()).unwrap();
 
-    let pixmap = if output.trim {
-        let (_, _, new_pixmap) = trim_transparency(pixmap.clone()).unwrap();
+    // Part of the emoji that is exported, at its own size:
+    let (x, y, width, height) = if output.trim {
+        let (x, y, trimmed) = trim_transparency(pixmap.clone()).unwrap();
 
-        new_pixmap
+        (x, y, trimmed.width() as usize, trimmed.height() as usize)
     } else {
-        pixmap.clone()
+        (0, 0, pixmap.width() as usize, pixmap.height() as usize)
     };
+    let pixmap = match output.size {
+        Some(size) => {
+            let zoom = zoom(width, height, size);
+            let (width, height) = fit(width, height, size);
+            let node = find_node(rtree, emoji, &emoji.id().unwrap());
+            let (x, y) = (x as f32 * zoom, y as f32 * zoom);
+
+            crop(
+                &rasterize(rtree, &node, zoom),
+                x.round() as i32,
+                y.round() as i32,
+                width,
+                height,
+            )
+        }
+        None => crop(pixmap, x, y, width, height),
+    };
+
+    pixmap.save_png(&path).unwrap();
 
-    // println!("Writing emoji to {}", path.to_str().unwrap());
-    pixmap.save_png(path).unwrap();
+    copy_aliases(emoji, &path, theme, output, "png");
 }
 
 pub fn render_animation(
     emoji: &Emoji,
     width: usize,
     height: usize,
-    frames: &Vec<(usize, usize, Pixmap)>,
+    frames: &[(usize, usize, Pixmap)],
+    rtree: &Tree,
     theme: &Theme,
     output: &Output,
 ) {
-    let dir = output.directory.join(&theme.name);
-    let webp_path = dir.join(format!("{}{}.webp", theme.prefix, emoji.name().unwrap()));
-    let gif_path = dir.join(format!("{}{}.gif", theme.prefix, emoji.name().unwrap()));
+    let webp_path = output.path(theme, emoji, &emoji.name().unwrap(), "webp");
+    let gif_path = output.path(theme, emoji, &emoji.name().unwrap(), "gif");
 
-    create_dir_all(&dir).unwrap();
+    create_dir_all(webp_path.parent().unwrap()).unwrap();
 
-    let (width, height, trim) = if output.trim {
+    // Part of the frames that is exported, at their own size:
+    let rect = if output.trim {
         // Calculate the actual animation size
-        let rect = frames.iter().fold(
+        frames.iter().fold(
             IntRect::from_ltrb(
                 (width / 2) as i32,
                 (height / 2) as i32,
@@ -203,21 +495,22 @@ pub fn render_animation(
             )
             .unwrap(),
             |bbox, (_, _, pixmap)| expand_rect(bbox, get_trim_rect(pixmap)),
-        );
-
-        (
-            rect.width() as usize,
-            rect.height() as usize,
-            IntRect::from_ltrb(
-                rect.left() as i32,
-                rect.top() as i32,
-                rect.right() as i32,
-                rect.bottom() as i32,
-            ),
         )
     } else {
-        (width, height, None)
+        IntRect::from_xywh(0, 0, width as u32, height as u32).unwrap()
     };
+    let (zoom, (width, height)) = match output.size {
+        Some(size) => {
+            let (width, height) = (rect.width() as usize, rect.height() as usize);
+
+            (zoom(width, height, size), fit(width, height, size))
+        }
+        None => (1.0, (rect.width() as usize, rect.height() as usize)),
+    };
+    let (x, y) = (
+        (rect.left() as f32 * zoom).round() as i32,
+        (rect.top() as f32 * zoom).round() as i32,
+    );
 
     let mut gif = File::create(&gif_path).unwrap();
     let mut gif_encoder = gif::Encoder::new(&mut gif, width as u16, height as u16, &[]).unwrap();
@@ -226,14 +519,13 @@ pub fn render_animation(
 
     gif_encoder.set_repeat(gif::Repeat::Infinite).unwrap();
 
-    // println!("Writing emoji to {}", webp_path.to_str().unwrap());
-    // println!("Writing emoji to {}", gif_path.to_str().unwrap());
+    for (frame, (_, delay, pixmap)) in emoji.frames().iter().zip(frames) {
+        let mut pixmap = if output.size.is_some() {
+            let node = find_node(rtree, emoji, &frame.id().unwrap());
 
-    for (_, delay, pixmap) in frames {
-        let mut pixmap = if let Some(trim) = trim {
-            pixmap.clone_rect(trim).unwrap()
+            crop(&rasterize(rtree, &node, zoom), x, y, width, height)
         } else {
-            pixmap.clone()
+            crop(pixmap, x, y, width, height)
         };
 
         webp_encoder
@@ -255,6 +547,54 @@ pub fn render_animation(
     let webp = webp_encoder.finalize(timestamp as i32).unwrap();
 
     std::fs::write(&webp_path, &webp).unwrap();
+
+    // Finish the gif before copying it:
+    drop(gif_encoder);
+
+    copy_aliases(emoji, &webp_path, theme, output, "webp");
+    copy_aliases(emoji, &gif_path, theme, output, "gif");
+}
+
+/// Write a copy of an exported file for every alias of the emoji
+fn copy_aliases(emoji: &Emoji, path: &Path, theme: &Theme, output: &Output, extension: &str) {
+    for alias in emoji.aliases() {
+        // Path patterns can put aliases in other directories:
+        let copy = output.path(theme, emoji, &alias, extension);
+
+        create_dir_all(copy.parent().unwrap()).unwrap();
+        std::fs::copy(path, copy).unwrap();
+    }
+}
+
+/// Largest size within a square of the given size that keeps the aspect ratio
+pub fn fit(width: usize, height: usize, size: u32) -> (usize, usize) {
+    let scale = zoom(width, height, size);
+
+    (
+        ((width as f32 * scale).round() as usize).max(1),
+        ((height as f32 * scale).round() as usize).max(1),
+    )
+}
+
+/// Zoom that makes the larger side of an emoji the given size
+fn zoom(width: usize, height: usize, size: u32) -> f32 {
+    size as f32 / width.max(height).max(1) as f32
+}
+
+/// Copy part of a pixmap, anything outside of it is left transparent
+fn crop(pixmap: &Pixmap, x: i32, y: i32, width: usize, height: usize) -> Pixmap {
+    let mut cropped = Pixmap::new(width as u32, height as u32).unwrap();
+
+    cropped.draw_pixmap(
+        -x,
+        -y,
+        pixmap.as_ref(),
+        &PixmapPaint::default(),
+        Transform::identity(),
+        None,
+    );
+
+    cropped
 }
 
 fn expand_rect(a: IntRect, b: IntRect) -> IntRect {
@@ -299,6 +639,11 @@ fn get_trim_rect(pixmap: &Pixmap) -> IntRect {
         }
     }
 
+    // Nothing is visible, so there's nothing to trim to:
+    if min_x > max_x || min_y > max_y {
+        return IntRect::from_xywh(0, 0, pixmap.width(), pixmap.height()).unwrap();
+    }
+
     // Expand in all directions by 1px.
     min_x = (min_x - 1).max(0);
     min_y = (min_y - 1).max(0);
@@ -0,0 +1,155 @@
+use crate::document::Emoji;
+use crate::manifest::{Gallery, OutputFormat, Project};
+use serde::Serialize;
+use std::fs::create_dir_all;
+use std::path::{Component, Path, PathBuf};
+use tinytemplate::TinyTemplate;
+
+#[derive(Serialize)]
+pub struct Renderable {
+    name: String,
+    themes: Vec<RenderableTheme>,
+}
+
+#[derive(Serialize)]
+pub struct RenderableTheme {
+    name: String,
+    outputs: Vec<RenderableOutput>,
+}
+
+#[derive(Serialize)]
+pub struct RenderableOutput {
+    directory: String,
+    emojis: Vec<RenderableEmoji>,
+}
+
+#[derive(Serialize)]
+pub struct RenderableEmoji {
+    name: String,
+    aliases: Vec<String>,
+    src: String,
+    is_animation: bool,
+    /// Every frame of an animation exported as svg, which has a file for each
+    frames: Vec<RenderableFrame>,
+}
+
+#[derive(Serialize)]
+pub struct RenderableFrame {
+    src: String,
+    delay: usize,
+}
+
+/// Collect the exported files of every theme and output, linked relative to the gallery
+pub fn process(project: &Project, emojis: &[Emoji], gallery: &Gallery) -> Renderable {
+    let mut emojis: Vec<_> = emojis
+        .iter()
+        .filter(|emoji| emoji.name().is_some())
+        .collect();
+
+    emojis.sort_by_key(|emoji| emoji.name());
+
+    // Number of directories between the gallery and the project root:
+    let depth = gallery
+        .directory
+        .strip_prefix(&project.path)
+        .unwrap_or(&gallery.directory)
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .count();
+
+    let themes = project
+        .themes
+        .iter()
+        .map(|theme| RenderableTheme {
+            name: theme.name.clone(),
+            outputs: project
+                .outputs
+                .iter()
+                .map(|output| RenderableOutput {
+                    directory: output.directory.display().to_string(),
+                    emojis: emojis
+                        .iter()
+                        .filter_map(|emoji| {
+                            let name = emoji.name().unwrap();
+                            let extensions = output.extensions(emoji);
+                            let src = |extension: &String| {
+                                to_url(depth, &output.path(theme, emoji, &name, extension))
+                            };
+                            let frames = if output.format == OutputFormat::Svg {
+                                emoji
+                                    .frames()
+                                    .iter()
+                                    .zip(&extensions)
+                                    .map(|(frame, extension)| RenderableFrame {
+                                        src: src(extension),
+                                        delay: frame.delay().unwrap_or_default(),
+                                    })
+                                    .collect()
+                            } else {
+                                Vec::new()
+                            };
+
+                            // Animations without frames don't export any files:
+                            Some(RenderableEmoji {
+                                name: output.name(theme, &name),
+                                aliases: emoji
+                                    .aliases()
+                                    .iter()
+                                    .map(|alias| output.name(theme, alias))
+                                    .collect(),
+                                src: src(extensions.first()?),
+                                is_animation: emoji.is_animation(),
+                                frames,
+                            })
+                        })
+                        .collect(),
+                })
+                .collect(),
+        })
+        .collect();
+
+    Renderable {
+        name: project.emojiset.name.clone(),
+        themes,
+    }
+}
+
+/// Path the gallery is written to
+pub fn path(gallery: &Gallery) -> PathBuf {
+    gallery.directory.join("index.html")
+}
+
+/// Write the gallery index.html and return its path
+pub fn render(context: &Renderable, gallery: &Gallery) -> PathBuf {
+    let path = path(gallery);
+
+    create_dir_all(&gallery.directory).unwrap();
+    std::fs::write(&path, to_html(context)).unwrap();
+
+    path
+}
+
+pub fn to_html(context: &Renderable) -> String {
+    let mut renderer = TinyTemplate::new();
+
+    renderer
+        .add_template("gallery", include_str!("../../tpl/gallery.html"))
+        .unwrap();
+
+    renderer.render("gallery", context).unwrap()
+}
+
+fn to_url(depth: usize, path: &Path) -> String {
+    let segments: Vec<String> = std::iter::repeat_n("..".to_string(), depth)
+        .chain(path.iter().map(|segment| {
+            segment
+                .to_string_lossy()
+                .replace('%', "%25")
+                .replace(' ', "%20")
+                .replace('#', "%23")
+                .replace('?', "%3F")
+        }))
+        .collect();
+
+    segments.join("/")
+}
@@ -1,2 +1,4 @@
 pub mod emoji_renderer;
+pub mod gallery_renderer;
+pub mod preview_renderer;
 pub mod template_renderer;
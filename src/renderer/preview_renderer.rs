@@ -0,0 +1,343 @@
+use crate::document::Emoji;
+use crate::manifest::{Preview, Theme};
+use crate::renderer::emoji_renderer::RenderableEmoji;
+use lazy_static::lazy_static;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+use tiny_skia::{FilterQuality, Pixmap, PixmapPaint, Transform};
+use webp_animation::prelude::*;
+
+const PADDING: u32 = 16;
+const LABEL_HEIGHT: u32 = 24;
+const FONT_SIZE: u32 = 12;
+
+lazy_static! {
+    static ref OPTIONS: usvg::Options = {
+        let mut opt = usvg::Options::default();
+
+        opt.fontdb.load_system_fonts();
+
+        // Fall back to any installed font when the default sans-serif font is missing:
+        let query = usvg::fontdb::Query {
+            families: &[usvg::fontdb::Family::SansSerif],
+            ..Default::default()
+        };
+
+        if opt.fontdb.query(&query).is_none() {
+            if let Some(family) = opt.fontdb.faces().first().map(|face| face.family.clone()) {
+                opt.fontdb.set_sans_serif_family(family);
+            }
+        }
+
+        opt
+    };
+}
+
+/// A labelled grid of every emoji in a theme, on each background. Emoji are
+/// drawn as they're added, so only animation frames are kept until saving.
+pub struct ContactSheet {
+    name: String,
+    preview: Preview,
+    cells: HashMap<String, usize>,
+    sheets: Vec<(&'static str, Pixmap)>,
+    animations: Vec<(usize, Vec<(usize, Pixmap)>)>,
+}
+
+impl ContactSheet {
+    pub fn new(emojis: &[Emoji], theme: &Theme, preview: &Preview) -> Self {
+        let backgrounds = [
+            ("light", &preview.light, "#000000"),
+            ("dark", &preview.dark, "#ffffff"),
+        ];
+
+        Self {
+            name: theme.name.clone(),
+            preview: preview.clone(),
+            cells: emojis
+                .iter()
+                .enumerate()
+                .map(|(index, emoji)| (emoji.id().unwrap(), index))
+                .collect(),
+            sheets: backgrounds
+                .iter()
+                .map(|(variant, background, text)| {
+                    (*variant, render_sheet(emojis, preview, background, text))
+                })
+                .collect(),
+            animations: Vec::new(),
+        }
+    }
+
+    /// Draw a rendered emoji into its cell on every sheet
+    pub fn add(&mut self, emoji: &RenderableEmoji) {
+        let index = match self.cells.get(&emoji.emoji().id().unwrap()) {
+            Some(index) => *index,
+            None => return,
+        };
+
+        match emoji {
+            RenderableEmoji::Image { pixmap, .. } => {
+                let (width, height) = (pixmap.width() as usize, pixmap.height() as usize);
+
+                for (_, sheet) in &mut self.sheets {
+                    draw_cell(sheet, &self.preview, index, pixmap, width, height);
+                }
+            }
+            RenderableEmoji::Animation {
+                width,
+                height,
+                frames,
+                ..
+            } => {
+                if self.preview.animated {
+                    // Keep cell sized copies of each frame for later:
+                    let frames = frames
+                        .iter()
+                        .map(|(_, delay, pixmap)| {
+                            let mut frame =
+                                Pixmap::new(self.preview.size, self.preview.size).unwrap();
+
+                            draw_scaled(
+                                &mut frame,
+                                0,
+                                0,
+                                self.preview.size,
+                                pixmap,
+                                *width,
+                                *height,
+                            );
+
+                            (*delay, frame)
+                        })
+                        .collect();
+
+                    self.animations.push((index, frames));
+                } else if let Some((_, _, pixmap)) = frames.first() {
+                    for (_, sheet) in &mut self.sheets {
+                        draw_cell(sheet, &self.preview, index, pixmap, *width, *height);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Write the sheets to the preview directory and return their paths
+    pub fn save(self) -> Vec<PathBuf> {
+        create_dir_all(&self.preview.directory).unwrap();
+
+        self.sheets
+            .iter()
+            .zip(paths(&self.name, &self.preview))
+            .map(|((_, sheet), path)| {
+                if self.preview.animated {
+                    std::fs::write(&path, self.render_animated(sheet)).unwrap();
+                } else {
+                    sheet.save_png(&path).unwrap();
+                }
+
+                path
+            })
+            .collect()
+    }
+
+    /// Draw the frame of every animation that is showing at the timestamp
+    fn draw_animations(&self, sheet: &mut Pixmap, at: usize) {
+        for (index, frames) in &self.animations {
+            let duration: usize = frames.iter().map(|(delay, _)| delay).sum();
+            let mut at = if duration > 0 { at % duration } else { 0 };
+
+            let frame = frames.iter().find(|(delay, _)| {
+                if at < *delay {
+                    return true;
+                }
+
+                at -= delay;
+                false
+            });
+
+            if let Some((_, pixmap)) = frame.or_else(|| frames.first()) {
+                let (x, y) = cell(&self.preview, *index);
+
+                sheet.draw_pixmap(
+                    x as i32,
+                    y as i32,
+                    pixmap.as_ref(),
+                    &PixmapPaint::default(),
+                    Transform::identity(),
+                    None,
+                );
+            }
+        }
+    }
+
+    /// Encode an animated webp sheet that loops for as long as the longest animation
+    fn render_animated(&self, sheet: &Pixmap) -> Vec<u8> {
+        let mut timestamps = BTreeSet::new();
+        let duration = self
+            .animations
+            .iter()
+            .map(|(_, frames)| frames.iter().map(|(delay, _)| delay).sum())
+            .max()
+            .unwrap_or(0);
+
+        timestamps.insert(0);
+
+        for (_, frames) in &self.animations {
+            let mut timestamp = 0;
+
+            for (delay, _) in frames.iter().cycle() {
+                if *delay == 0 || timestamp >= duration {
+                    break;
+                }
+
+                timestamps.insert(timestamp);
+                timestamp += delay;
+            }
+        }
+
+        let mut encoder = Encoder::new((sheet.width(), sheet.height())).unwrap();
+
+        for timestamp in timestamps {
+            let mut frame = sheet.clone();
+
+            self.draw_animations(&mut frame, timestamp);
+            encoder.add_frame(frame.data(), timestamp as i32).unwrap();
+        }
+
+        encoder.finalize(duration.max(1) as i32).unwrap().to_vec()
+    }
+}
+
+/// Paths of the light and dark sheets of a theme
+pub fn paths(theme: &str, preview: &Preview) -> Vec<PathBuf> {
+    let extension = if preview.animated { "webp" } else { "png" };
+
+    ["light", "dark"]
+        .iter()
+        .map(|variant| {
+            preview
+                .directory
+                .join(format!("{}-{}.{}", theme, variant, extension))
+        })
+        .collect()
+}
+
+/// Width and height of a sheet holding this many emoji
+pub fn size(emojis: usize, preview: &Preview) -> (u32, u32) {
+    let columns = preview.columns.max(1) as u32;
+    let rows = (emojis as u32).div_ceil(columns);
+
+    (
+        PADDING + columns * (preview.size + PADDING),
+        PADDING + rows.max(1) * (preview.size + LABEL_HEIGHT + PADDING),
+    )
+}
+
+/// Render the background and labels of an empty sheet
+fn render_sheet(emojis: &[Emoji], preview: &Preview, background: &str, text: &str) -> Pixmap {
+    let (width, height) = size(emojis.len(), preview);
+    // Always leave room for at least the ellipsis, even in the smallest cells:
+    let max_chars = ((preview.size / (FONT_SIZE / 2)) as usize).max(1);
+
+    let labels: String = emojis
+        .iter()
+        .enumerate()
+        .map(|(index, emoji)| {
+            let (x, y) = cell(preview, index);
+            let name = emoji.name().unwrap();
+            let name = if name.chars().count() > max_chars {
+                format!("{}…", name.chars().take(max_chars - 1).collect::<String>())
+            } else {
+                name
+            };
+
+            format!(
+                r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+                x + preview.size / 2,
+                y + preview.size + LABEL_HEIGHT - (LABEL_HEIGHT - FONT_SIZE) / 2,
+                escape_xml(&name)
+            )
+        })
+        .collect();
+
+    let svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">
+<rect width="{w}" height="{h}" fill="{}"/>
+<g font-family="sans-serif" font-size="{}" fill="{}">{}</g>
+</svg>"#,
+        escape_xml(background),
+        FONT_SIZE,
+        escape_xml(text),
+        labels,
+        w = width,
+        h = height,
+    );
+
+    let rtree = usvg::Tree::from_str(&svg, &OPTIONS.to_ref()).unwrap();
+    let mut sheet = Pixmap::new(width, height).unwrap();
+
+    resvg::render(&rtree, usvg::FitTo::Original, sheet.as_mut()).unwrap();
+
+    sheet
+}
+
+/// Scale an emoji to fit its grid cell and draw it centred
+fn draw_cell(
+    sheet: &mut Pixmap,
+    preview: &Preview,
+    index: usize,
+    pixmap: &Pixmap,
+    width: usize,
+    height: usize,
+) {
+    let (x, y) = cell(preview, index);
+
+    draw_scaled(sheet, x, y, preview.size, pixmap, width, height);
+}
+
+fn draw_scaled(
+    target: &mut Pixmap,
+    x: u32,
+    y: u32,
+    size: u32,
+    pixmap: &Pixmap,
+    width: usize,
+    height: usize,
+) {
+    let size = size as f32;
+    let scale = (size / width.max(1) as f32).min(size / height.max(1) as f32);
+    let offset_x = x as f32 + (size - width as f32 * scale) / 2.0;
+    let offset_y = y as f32 + (size - height as f32 * scale) / 2.0;
+    let paint = PixmapPaint {
+        quality: FilterQuality::Bicubic,
+        ..Default::default()
+    };
+
+    target.draw_pixmap(
+        0,
+        0,
+        pixmap.as_ref(),
+        &paint,
+        Transform::from_row(scale, 0.0, 0.0, scale, offset_x, offset_y),
+        None,
+    );
+}
+
+fn cell(preview: &Preview, index: usize) -> (u32, u32) {
+    let columns = preview.columns.max(1);
+    let column = (index % columns) as u32;
+    let row = (index / columns) as u32;
+
+    (
+        PADDING + column * (preview.size + PADDING),
+        PADDING + row * (preview.size + LABEL_HEIGHT + PADDING),
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
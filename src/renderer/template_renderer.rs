@@ -19,8 +19,19 @@ pub struct Renderable {
 pub struct RenderableEmoji {
     id: String,
     name: String,
+    aliases: Vec<String>,
     is_animation: bool,
     is_image: bool,
+    exports: Vec<RenderableExport>,
+}
+
+/// Names an emoji is exported with for a theme and output
+#[derive(Serialize)]
+pub struct RenderableExport {
+    theme: String,
+    directory: PathBuf,
+    name: String,
+    aliases: Vec<String>,
 }
 
 pub trait OnProgress<'a>: Fn(&'a Template) {}
@@ -30,20 +41,29 @@ impl<'a, T> OnProgress<'a> for T where T: Fn(&'a Template) {}
 pub fn process(project: &Project, emojis: &Vec<Emoji>) -> Renderable {
     let mut renderable_emoji: Vec<RenderableEmoji> = emojis
         .par_iter()
-        .filter_map(|emoji| match emoji {
-            Emoji::Animation { id, name, .. } => Some(RenderableEmoji {
-                id: id.clone(),
-                name: name.clone(),
-                is_animation: true,
-                is_image: false,
-            }),
-            Emoji::Image { id, name, .. } => Some(RenderableEmoji {
-                id: id.clone(),
-                name: name.clone(),
-                is_animation: false,
-                is_image: true,
-            }),
-            _ => None,
+        .filter(|emoji| emoji.is_image() || emoji.is_animation())
+        .map(|emoji| RenderableEmoji {
+            id: emoji.id().unwrap(),
+            name: emoji.name().unwrap(),
+            aliases: emoji.aliases(),
+            is_animation: emoji.is_animation(),
+            is_image: emoji.is_image(),
+            exports: project
+                .themes
+                .iter()
+                .flat_map(|theme| {
+                    project.outputs.iter().map(move |output| RenderableExport {
+                        theme: theme.name.clone(),
+                        directory: output.directory.clone(),
+                        name: output.name(theme, &emoji.name().unwrap()),
+                        aliases: emoji
+                            .aliases()
+                            .iter()
+                            .map(|alias| output.name(theme, alias))
+                            .collect(),
+                    })
+                })
+                .collect(),
         })
         .collect();
 
@@ -77,11 +97,11 @@ where
             Ok(output) => {
                 std::fs::write(&output_path, output).unwrap();
             }
-            Err(error) => {
-                println!("{}", error);
-
-                std::process::exit(1);
-            }
+            Err(error) => panic!(
+                "error rendering template {}: {}",
+                input_path.display(),
+                error
+            ),
         }
 
         on_progress(template);